@@ -0,0 +1,83 @@
+use super::{Auth, RequestBuilder};
+use reqwest::header::HeaderValue;
+use std::sync::Arc;
+
+/// Where an [`ApiKey`] is placed in the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyPlacement {
+    /// A custom header such as `X-Api-Key`.
+    Header,
+    /// A query parameter such as `?api_key=`.
+    Query,
+}
+
+/// A cheaply clonable API key sent in a custom header or query parameter.
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    /// Name of the header or query parameter.
+    pub name: Arc<str>,
+    pub key: Arc<str>,
+    pub placement: KeyPlacement,
+}
+
+impl ApiKey {
+    /// Sends the key in the header with the provided name.
+    #[must_use]
+    pub fn header(name: &str, key: &str) -> Self {
+        Self {
+            name: name.into(),
+            key: key.into(),
+            placement: KeyPlacement::Header,
+        }
+    }
+
+    /// Sends the key in the query parameter with the provided name.
+    #[must_use]
+    pub fn query(name: &str, key: &str) -> Self {
+        Self {
+            name: name.into(),
+            key: key.into(),
+            placement: KeyPlacement::Query,
+        }
+    }
+}
+
+impl Auth for ApiKey {
+    fn attach(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.placement {
+            KeyPlacement::Header => match HeaderValue::from_str(&self.key) {
+                Ok(mut key) => {
+                    key.set_sensitive(true);
+                    request.header(self.name.as_ref(), key)
+                }
+                // let the builder report the invalid value
+                Err(_) => request.header(self.name.as_ref(), self.key.as_ref()),
+            },
+            KeyPlacement::Query => request.query(&[(self.name.as_ref(), self.key.as_ref())]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::Client;
+
+    fn attach<A: Auth>(auth: &A) -> reqwest::Request {
+        auth.attach(Client::new().get("http://localhost/items?page=2"))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn placement() {
+        let request = attach(&ApiKey::header("X-Api-Key", "secret"));
+        assert_eq!(request.headers()["x-api-key"], "secret");
+        assert!(request.headers()["x-api-key"].is_sensitive());
+        assert_eq!(request.url().query(), Some("page=2"));
+
+        let request = attach(&ApiKey::query("api_key", "s3cr3t&"));
+        assert!(request.headers().is_empty());
+        assert_eq!(request.url().query(), Some("page=2&api_key=s3cr3t%26"));
+    }
+}
@@ -0,0 +1,444 @@
+use super::buffer_body;
+use crate::ApiResult;
+use crate::middleware::{BoxFuture, Middleware, Next};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use reqwest::Request;
+use reqwest::header::{HeaderName, HeaderValue};
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The canonical string signed by default: timestamp, method, path and body concatenated.
+const DEFAULT_TEMPLATE: &str = "{timestamp}{method}{path}{body}";
+
+/// How the request timestamp is rendered into the canonical string and its header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Unix time in seconds.
+    #[default]
+    Seconds,
+    /// Unix time in milliseconds.
+    Millis,
+}
+
+/// How the signature is encoded into its header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignatureEncoding {
+    /// Lowercase hex.
+    #[default]
+    Hex,
+    /// Standard base64 with padding.
+    Base64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Method,
+    Path,
+    Query,
+    Timestamp,
+    Nonce,
+    Body,
+    BodySha256,
+}
+
+impl Part {
+    fn parse_template(template: &str) -> Result<Vec<Self>, InvalidSigner> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Self::Literal(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find('}') else {
+                return Err(InvalidSigner::UnclosedPlaceholder(template.to_string()));
+            };
+            let end = start + end;
+            parts.push(match &rest[start + 1..end] {
+                "method" => Self::Method,
+                "path" => Self::Path,
+                "query" => Self::Query,
+                "timestamp" => Self::Timestamp,
+                "nonce" => Self::Nonce,
+                "body" => Self::Body,
+                "body_sha256" => Self::BodySha256,
+                other => return Err(InvalidSigner::UnknownPlaceholder(other.to_string())),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Self::Literal(rest.to_string()));
+        }
+        Ok(parts)
+    }
+}
+
+/// A setting of a [`HmacSignerBuilder`] that cannot be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidSigner {
+    /// The template contains a placeholder without a closing brace.
+    UnclosedPlaceholder(String),
+    /// The template contains an unknown placeholder.
+    UnknownPlaceholder(String),
+    /// A header name is not a valid HTTP header name.
+    HeaderName(String),
+    /// The key cannot be sent as a header value.
+    Key,
+}
+
+impl fmt::Display for InvalidSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnclosedPlaceholder(template) => {
+                write!(f, "unclosed placeholder in template '{template}'")
+            }
+            Self::UnknownPlaceholder(placeholder) => {
+                write!(f, "unknown placeholder '{{{placeholder}}}' in template")
+            }
+            Self::HeaderName(header) => write!(f, "invalid header name '{header}'"),
+            Self::Key => write!(f, "key is not a valid header value"),
+        }
+    }
+}
+
+impl StdError for InvalidSigner {}
+
+/// Signs every request with HMAC-SHA256 over a canonical string.
+///
+/// The canonical string is rendered from a template with the placeholders `{method}`, `{path}`,
+/// `{query}` (without the leading `?`), `{timestamp}`, `{nonce}`, `{body}` and `{body_sha256}`
+/// (lowercase hex), by default `{timestamp}{method}{path}{body}`. The signature is sent in the
+/// `X-Signature` header and the timestamp in `X-Timestamp`, both of which can be renamed.
+///
+/// If the template signs the body, streaming bodies such as multipart uploads are read into
+/// memory before signing.
+///
+/// # Examples
+/// ```
+/// # use bc_api_client::auth::{HmacSigner, SignatureEncoding, TimestampFormat};
+/// let signer = HmacSigner::builder("my-secret")
+///     .with_key("x-api-key", "my-key")
+///     .with_template("{timestamp}\n{method}\n{path}\n{body_sha256}")
+///     .with_timestamp_format(TimestampFormat::Millis)
+///     .with_encoding(SignatureEncoding::Base64)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct HmacSigner {
+    secret: Arc<[u8]>,
+    template: Arc<[Part]>,
+    key: Option<(HeaderName, HeaderValue)>,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+    nonce_header: Option<HeaderName>,
+    timestamp_format: TimestampFormat,
+    encoding: SignatureEncoding,
+}
+
+impl HmacSigner {
+    /// Starts configuring a signer with the provided secret.
+    pub fn builder<S: AsRef<[u8]>>(secret: S) -> HmacSignerBuilder {
+        HmacSignerBuilder {
+            secret: secret.as_ref().into(),
+            template: DEFAULT_TEMPLATE.to_string(),
+            key: None,
+            signature_header: "x-signature".to_string(),
+            timestamp_header: "x-timestamp".to_string(),
+            nonce_header: None,
+            timestamp_format: TimestampFormat::default(),
+            encoding: SignatureEncoding::default(),
+        }
+    }
+
+    /// Returns whether the template covers the request body.
+    fn signs_body(&self) -> bool {
+        self.template
+            .iter()
+            .any(|part| matches!(part, Part::Body | Part::BodySha256))
+    }
+
+    /// Renders the canonical string of a request.
+    fn canonical(&self, request: &Request, timestamp: &str, nonce: &str) -> Vec<u8> {
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .unwrap_or_default();
+        let mut canonical = Vec::new();
+        for part in self.template.iter() {
+            match part {
+                Part::Literal(literal) => canonical.extend_from_slice(literal.as_bytes()),
+                Part::Method => canonical.extend_from_slice(request.method().as_str().as_bytes()),
+                Part::Path => canonical.extend_from_slice(request.url().path().as_bytes()),
+                Part::Query => {
+                    canonical.extend_from_slice(request.url().query().unwrap_or("").as_bytes());
+                }
+                Part::Timestamp => canonical.extend_from_slice(timestamp.as_bytes()),
+                Part::Nonce => canonical.extend_from_slice(nonce.as_bytes()),
+                Part::Body => canonical.extend_from_slice(body),
+                Part::BodySha256 => {
+                    canonical.extend_from_slice(hex::encode(bc_hash::sha2_256(body)).as_bytes());
+                }
+            }
+        }
+        canonical
+    }
+
+    /// Signs the request with the provided timestamp and nonce.
+    fn sign(&self, mut request: Request, timestamp: &str, nonce: &str) -> Request {
+        let signature =
+            bc_hash::hmac::sha256::sign(&self.secret, self.canonical(&request, timestamp, nonce));
+        let signature = match self.encoding {
+            SignatureEncoding::Hex => hex::encode(signature),
+            SignatureEncoding::Base64 => BASE64.encode(signature),
+        };
+
+        let headers = request.headers_mut();
+        if let Some((header, key)) = &self.key {
+            headers.insert(header, key.clone());
+        }
+        // hex, base64, digits and the generated nonce are always valid header values
+        headers.insert(&self.timestamp_header, header_value(timestamp));
+        if let Some(header) = &self.nonce_header {
+            headers.insert(header, header_value(nonce));
+        }
+        headers.insert(&self.signature_header, header_value(&signature));
+        request
+    }
+
+    fn timestamp(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match self.timestamp_format {
+            TimestampFormat::Seconds => now.as_secs().to_string(),
+            TimestampFormat::Millis => now.as_millis().to_string(),
+        }
+    }
+}
+
+/// Configures a [`HmacSigner`], whose settings are validated by [`HmacSignerBuilder::build`].
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct HmacSignerBuilder {
+    secret: Arc<[u8]>,
+    template: String,
+    key: Option<(String, String)>,
+    signature_header: String,
+    timestamp_header: String,
+    nonce_header: Option<String>,
+    timestamp_format: TimestampFormat,
+    encoding: SignatureEncoding,
+}
+
+impl HmacSignerBuilder {
+    /// Sends the key identifying the secret in the provided header.
+    pub fn with_key(mut self, header: &str, key: &str) -> Self {
+        self.key = Some((header.to_string(), key.to_string()));
+        self
+    }
+
+    /// Sets the template of the signed canonical string.
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    /// Sets the header the signature is sent in.
+    pub fn with_signature_header(mut self, header: &str) -> Self {
+        self.signature_header = header.to_string();
+        self
+    }
+
+    /// Sets the header the timestamp is sent in.
+    pub fn with_timestamp_header(mut self, header: &str) -> Self {
+        self.timestamp_header = header.to_string();
+        self
+    }
+
+    /// Generates a random nonce for every request and sends it in the provided header.
+    pub fn with_nonce_header(mut self, header: &str) -> Self {
+        self.nonce_header = Some(header.to_string());
+        self
+    }
+
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: SignatureEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Validates the settings and creates the signer.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the template contains an unknown or unclosed placeholder, a header name is
+    /// invalid or the key is not a valid header value.
+    pub fn build(self) -> Result<HmacSigner, InvalidSigner> {
+        let key = match &self.key {
+            Some((header, key)) => {
+                let mut key = HeaderValue::from_str(key).map_err(|_| InvalidSigner::Key)?;
+                key.set_sensitive(true);
+                Some((header_name(header)?, key))
+            }
+            None => None,
+        };
+        Ok(HmacSigner {
+            secret: self.secret,
+            template: Part::parse_template(&self.template)?.into(),
+            key,
+            signature_header: header_name(&self.signature_header)?,
+            timestamp_header: header_name(&self.timestamp_header)?,
+            nonce_header: self.nonce_header.as_deref().map(header_name).transpose()?,
+            timestamp_format: self.timestamp_format,
+            encoding: self.encoding,
+        })
+    }
+}
+
+impl Middleware for HmacSigner {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+        Box::pin(async move {
+            let request = if self.signs_body() {
+                buffer_body(request).await?
+            } else {
+                request
+            };
+            let nonce = hex::encode(rand::random::<[u8; 16]>());
+            let request = self.sign(request, &self.timestamp(), &nonce);
+            next.run(request).await
+        })
+    }
+}
+
+fn header_name(header: &str) -> Result<HeaderName, InvalidSigner> {
+    HeaderName::from_bytes(header.as_bytes())
+        .map_err(|_| InvalidSigner::HeaderName(header.to_string()))
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).expect("invalid header value")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::{Client, Method};
+
+    fn build(method: Method, url: &str, body: &'static str) -> Request {
+        Client::new()
+            .request(method, url)
+            .body(body)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn canonical_string() {
+        let signer = HmacSigner::builder("secret")
+            .with_template("{timestamp}|{nonce}|{method}|{path}|{query}|{body}|{body_sha256}")
+            .build()
+            .unwrap();
+        let request = build(Method::POST, "http://localhost/orders?limit=5", "{}");
+        assert_eq!(
+            String::from_utf8(signer.canonical(&request, "1700000000", "abc")).unwrap(),
+            "1700000000|abc|POST|/orders|limit=5|{}|\
+             44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+
+        let signer = HmacSigner::builder("secret").build().unwrap();
+        let request = build(Method::GET, "http://localhost/orders", "");
+        assert_eq!(
+            signer.canonical(&request, "1700000000", "abc"),
+            b"1700000000GET/orders"
+        );
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let build = |builder: HmacSignerBuilder| builder.build().unwrap_err();
+        let builder = || HmacSigner::builder("secret");
+        assert_eq!(
+            build(builder().with_template("{host}{path}")),
+            InvalidSigner::UnknownPlaceholder("host".to_string())
+        );
+        assert_eq!(
+            build(builder().with_template("{path")),
+            InvalidSigner::UnclosedPlaceholder("{path".to_string())
+        );
+        assert_eq!(
+            build(builder().with_nonce_header("x nonce")),
+            InvalidSigner::HeaderName("x nonce".to_string())
+        );
+        assert_eq!(
+            build(builder().with_key("x-api-key", "line\nbreak")),
+            InvalidSigner::Key
+        );
+    }
+
+    #[test]
+    fn signature_headers() {
+        let body = "The quick brown fox jumps over the lazy dog";
+        let signer = HmacSigner::builder("key")
+            .with_template("{body}")
+            .with_key("x-api-key", "my-key")
+            .with_nonce_header("x-nonce")
+            .build()
+            .unwrap();
+        let request = signer.sign(build(Method::PUT, "http://localhost", body), "42", "n0");
+        let header = |name: &str| request.headers().get(name).unwrap().to_str().unwrap();
+        assert_eq!(
+            header("x-signature"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(header("x-timestamp"), "42");
+        assert_eq!(header("x-nonce"), "n0");
+        assert_eq!(header("x-api-key"), "my-key");
+
+        let signer = HmacSigner::builder("key")
+            .with_template("{body}")
+            .with_signature_header("sig")
+            .with_encoding(SignatureEncoding::Base64)
+            .build()
+            .unwrap();
+        let request = signer.sign(build(Method::POST, "http://localhost", body), "42", "n0");
+        assert_eq!(
+            request.headers()["sig"],
+            "97yD9DBThCSxMpjmqm+xQ+9NWaFJRhdZl0edvC0aPNg="
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_body_is_signed() {
+        let body = "The quick brown fox jumps over the lazy dog";
+        let chunks = futures_util::stream::iter(
+            body.as_bytes()
+                .chunks(8)
+                .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec())),
+        );
+        let request = Client::new()
+            .put("http://localhost")
+            .body(reqwest::Body::wrap_stream(chunks))
+            .build()
+            .unwrap();
+
+        let signer = HmacSigner::builder("key")
+            .with_template("{body}")
+            .build()
+            .unwrap();
+        let request = signer.sign(buffer_body(request).await.unwrap(), "42", "n0");
+        assert_eq!(
+            request.headers()["x-signature"],
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(request.body().unwrap().as_bytes(), Some(body.as_bytes()));
+    }
+}
@@ -1,13 +1,28 @@
+mod api_key;
 mod basic;
 mod bearer;
 mod headers;
+mod hmac;
+mod oauth2;
+mod sigv4;
 
+pub use api_key::{ApiKey, KeyPlacement};
 pub use basic::Basic;
 pub use bearer::Bearer;
+pub use hmac::{HmacSigner, HmacSignerBuilder, InvalidSigner, SignatureEncoding, TimestampFormat};
+pub use oauth2::OAuth2;
+pub use sigv4::SigV4;
 
-use reqwest::RequestBuilder;
+use http_body_util::BodyExt;
+use reqwest::{Request, RequestBuilder};
 
 /// Defines an authentication method that can be attached to a http request.
+///
+/// Every authentication method is a [`Middleware`](crate::middleware::Middleware) too, which
+/// attaches itself to the request once all other middleware of the client has run.
+///
+/// Tuples of up to four methods are middleware running all of them in order, e.g. an [`ApiKey`]
+/// along with [`Basic`] or [`OAuth2`] auth.
 pub trait Auth {
     fn attach(&self, request: RequestBuilder) -> RequestBuilder;
 }
@@ -17,3 +32,12 @@ impl Auth for () {
         request
     }
 }
+
+/// Reads a streaming body into memory, so that it can be signed.
+async fn buffer_body(mut request: Request) -> reqwest::Result<Request> {
+    if let Some(body) = request.body_mut().take_if(|body| body.as_bytes().is_none()) {
+        let body = body.collect().await?.to_bytes();
+        *request.body_mut() = Some(body.into());
+    }
+    Ok(request)
+}
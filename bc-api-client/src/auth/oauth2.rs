@@ -0,0 +1,336 @@
+use crate::middleware::{BoxFuture, Middleware, Next};
+use crate::request::read_response;
+use crate::{ApiError, ApiResult};
+use reqwest::header::{AUTHORIZATION, HeaderValue};
+use reqwest::{Client, Request, StatusCode};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+struct Token {
+    access_token: AccessToken,
+    expires_at: Option<Instant>,
+}
+
+/// An access token along with the `Authorization` header carrying it.
+#[derive(Clone, Debug)]
+struct AccessToken {
+    token: Arc<str>,
+    header: HeaderValue,
+}
+
+impl<'de> Deserialize<'de> for AccessToken {
+    /// Rejects tokens that cannot be sent in a header, which makes fetching them fail with a
+    /// decode error rather than sending calls without authentication.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        let mut header = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|_| D::Error::custom("access token is not a valid header value"))?;
+        header.set_sensitive(true);
+        Ok(Self {
+            token: token.into(),
+            header,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: AccessToken,
+    expires_in: Option<u64>,
+}
+
+/// OAuth 2.0 client credentials authentication with automatic token refresh.
+///
+/// Access tokens are fetched from the token endpoint on first use and cached until shortly
+/// before they expire. Concurrent calls wait for a single token request instead of fetching a
+/// token each. If the API rejects a token with `401 Unauthorized` anyway, a new token is fetched
+/// and the call is retried once. Clones share the same token cache.
+#[derive(Clone, Debug)]
+pub struct OAuth2 {
+    pub token_url: Arc<str>,
+    pub client_id: Arc<str>,
+    pub client_secret: Arc<str>,
+    pub scope: Option<Arc<str>>,
+    refresh_margin: Duration,
+    body_credentials: bool,
+    token: Arc<Mutex<Option<Token>>>,
+}
+
+impl OAuth2 {
+    #[must_use]
+    pub fn new(token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        Self {
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scope: None,
+            refresh_margin: Duration::from_secs(30),
+            body_credentials: false,
+            token: Arc::default(),
+        }
+    }
+
+    /// Sets the space separated scopes requested for the token.
+    #[must_use]
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Sets how long before its expiry a token is replaced by a fresh one.
+    #[must_use]
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Sends the client credentials as form fields instead of basic auth, for token endpoints
+    /// that do not support the latter.
+    #[must_use]
+    pub fn with_body_credentials(mut self) -> Self {
+        self.body_credentials = true;
+        self
+    }
+
+    /// Returns a valid access token, fetching a new one if there is none or it is about to
+    /// expire.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the token request fails or the token endpoint responds with an unexpected body,
+    /// including a token that cannot be sent in a header.
+    pub async fn access_token(&self, client: &Client) -> Result<Arc<str>, ApiError> {
+        Ok(self.token(client).await?.token)
+    }
+
+    async fn token(&self, client: &Client) -> Result<AccessToken, ApiError> {
+        // holding the lock while fetching makes concurrent calls wait for the same token
+        let mut token = self.token.lock().await;
+        if let Some(token) = token.as_ref().filter(|token| self.is_fresh(token)) {
+            return Ok(token.access_token.clone());
+        }
+
+        let response = self.fetch(client).await?.body;
+        let fresh = Token {
+            access_token: response.access_token,
+            expires_at: response
+                .expires_in
+                .map(|expires_in| Instant::now() + Duration::from_secs(expires_in)),
+        };
+        let access_token = fresh.access_token.clone();
+        *token = Some(fresh);
+        Ok(access_token)
+    }
+
+    fn is_fresh(&self, token: &Token) -> bool {
+        token
+            .expires_at
+            .is_none_or(|expires_at| Instant::now() + self.refresh_margin < expires_at)
+    }
+
+    async fn fetch(&self, client: &Client) -> ApiResult<TokenResponse> {
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        let mut request = client.post(self.token_url.as_ref());
+        if self.body_credentials {
+            form.push(("client_id", &self.client_id));
+            form.push(("client_secret", &self.client_secret));
+        } else {
+            request = request.basic_auth(&self.client_id, Some(&self.client_secret));
+        }
+
+        read_response(request.form(&form).send().await?)
+            .await?
+            .try_into_json()
+    }
+
+    /// Drops the cached token unless it has been replaced already.
+    async fn invalidate(&self, access_token: &Arc<str>) {
+        let mut token = self.token.lock().await;
+        if token
+            .as_ref()
+            .is_some_and(|token| Arc::ptr_eq(&token.access_token.token, access_token))
+        {
+            *token = None;
+        }
+    }
+}
+
+impl Middleware for OAuth2 {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+        Box::pin(async move {
+            let retry = request.try_clone();
+            let access_token = self.token(next.client()).await?;
+            let result = next.run(with_bearer(request, &access_token)).await;
+
+            match (result, retry) {
+                (Err(error), Some(request)) if error.status() == Some(StatusCode::UNAUTHORIZED) => {
+                    self.invalidate(&access_token.token).await;
+                    let access_token = self.token(next.client()).await?;
+                    next.run(with_bearer(request, &access_token)).await
+                }
+                (result, _) => result,
+            }
+        })
+    }
+}
+
+fn with_bearer(mut request: Request, access_token: &AccessToken) -> Request {
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, access_token.header.clone());
+    request
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClientBuilder;
+    use crate::request::Request as _;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Issues `token-<n>` from `/token` and accepts tokens issued at or after `valid_from` on
+    /// every other route.
+    #[derive(Default)]
+    struct Server {
+        issued: AtomicUsize,
+        valid_from: AtomicUsize,
+    }
+
+    impl Server {
+        async fn start(expires_in: u64) -> (String, Arc<Self>) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = Arc::new(Self::default());
+            let state = Arc::clone(&server);
+            tokio::spawn(async move {
+                loop {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let state = Arc::clone(&state);
+                    tokio::spawn(async move {
+                        let mut buf = [0u8; 4096];
+                        let read = stream.read(&mut buf).await.unwrap();
+                        let head = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                        let response = state.respond(&head, expires_in).await;
+                        stream.write_all(response.as_bytes()).await.unwrap();
+                        stream.shutdown().await.unwrap();
+                    });
+                }
+            });
+            (format!("http://{addr}"), server)
+        }
+
+        async fn respond(&self, head: &str, expires_in: u64) -> String {
+            let (status, body) = if head.starts_with("post /token") {
+                // base64 of "id:secret", lowercased along with the rest of the head
+                assert!(head.contains("authorization: basic awq6c2vjcmv0"));
+                // gives concurrent calls the chance to pile up
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let token = self.issued.fetch_add(1, Ordering::SeqCst) + 1;
+                let body = format!(
+                    r#"{{"access_token":"token-{token}","token_type":"bearer","expires_in":{expires_in}}}"#
+                );
+                ("200 OK", body)
+            } else {
+                let valid_from = self.valid_from.load(Ordering::SeqCst);
+                let valid = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("authorization: bearer token-"))
+                    .and_then(|token| token.trim().parse::<usize>().ok())
+                    .is_some_and(|token| token >= valid_from);
+                if valid {
+                    ("200 OK", "ok".to_string())
+                } else {
+                    ("401 Unauthorized", String::new())
+                }
+            };
+            format!(
+                "HTTP/1.1 {status}\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            )
+        }
+
+        fn issued(&self) -> usize {
+            self.issued.load(Ordering::SeqCst)
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_token() {
+        let (url, server) = Server::start(3600).await;
+        let auth = OAuth2::new(&format!("{url}/token"), "id", "secret");
+        let client = ApiClientBuilder::new(&url).with_auth(auth).build::<()>();
+
+        for _ in 0..3 {
+            client.clone().get("/data").request().await.unwrap();
+        }
+        assert_eq!(server.issued(), 1);
+
+        // concurrent calls share a single token request
+        let client = client.with_auth_cloned(OAuth2::new(&format!("{url}/token"), "id", "secret"));
+        let mut calls = tokio::task::JoinSet::new();
+        for _ in 0..5 {
+            let client = client.clone();
+            calls.spawn(async move { client.get("/data").request().await.map(|_| ()) });
+        }
+        while let Some(result) = calls.join_next().await {
+            result.unwrap().unwrap();
+        }
+        assert_eq!(server.issued(), 2);
+    }
+
+    #[tokio::test]
+    async fn refreshes_before_expiry() {
+        let (url, server) = Server::start(10).await;
+        let auth = OAuth2::new(&format!("{url}/token"), "id", "secret")
+            .with_refresh_margin(Duration::from_secs(15));
+        let client = ApiClientBuilder::new(&url).with_auth(auth).build::<()>();
+
+        client.clone().get("/data").request().await.unwrap();
+        client.get("/data").request().await.unwrap();
+        assert_eq!(server.issued(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_once_on_unauthorized() {
+        let (url, server) = Server::start(3600).await;
+        let auth = OAuth2::new(&format!("{url}/token"), "id", "secret");
+        let client = ApiClientBuilder::new(&url).with_auth(auth).build::<()>();
+        client.clone().get("/data").request().await.unwrap();
+
+        // revoke the cached token
+        server.valid_from.store(2, Ordering::SeqCst);
+        client.clone().get("/data").request().await.unwrap();
+        assert_eq!(server.issued(), 2);
+
+        // revoke all tokens, the retry fails as well
+        server.valid_from.store(usize::MAX, Ordering::SeqCst);
+        let error = client.get("/data").request().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(server.issued(), 3);
+    }
+
+    #[test]
+    fn rejects_tokens_unfit_for_headers() {
+        let response: TokenResponse =
+            serde_json::from_str(r#"{"access_token":"abc","expires_in":60}"#).unwrap();
+        assert_eq!(response.access_token.header, "Bearer abc");
+        assert!(response.access_token.header.is_sensitive());
+
+        let error = serde_json::from_str::<TokenResponse>(r#"{"access_token":"abc\ndef"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("not a valid header value"));
+    }
+}
@@ -0,0 +1,499 @@
+use crate::ApiResult;
+use crate::middleware::{BoxFuture, Middleware, Next};
+use crate::response::Response;
+use crate::retry::parse_http_date;
+use reqwest::header::{
+    CACHE_CONTROL, CONTENT_LENGTH, ETAG, HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Method, Request, StatusCode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// A response stored by an [`HttpCache`].
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub response: Response<Vec<u8>>,
+    /// The request headers named by the `Vary` header of the response, as they were sent.
+    pub vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    /// When the response stops being fresh and has to be revalidated.
+    pub expires: SystemTime,
+}
+
+impl CachedResponse {
+    /// Returns whether the response was stored for a request with the same varying headers.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+}
+
+/// The storage backend of an [`HttpCache`], keyed by request url.
+pub trait CacheStore: Send + Sync {
+    fn get(&self, key: &str) -> Option<CachedResponse>;
+    fn insert(&self, key: &str, response: CachedResponse);
+    fn remove(&self, key: &str);
+}
+
+impl<S: CacheStore + ?Sized> CacheStore for Arc<S> {
+    fn get(&self, key: &str) -> Option<CachedResponse> {
+        (**self).get(key)
+    }
+
+    fn insert(&self, key: &str, response: CachedResponse) {
+        (**self).insert(key, response);
+    }
+
+    fn remove(&self, key: &str) {
+        (**self).remove(key);
+    }
+}
+
+/// Keeps cached responses in memory, the default backend of an [`HttpCache`].
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedResponse>> {
+        // entries are replaced as a whole, so poisoning can be ignored
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<CachedResponse> {
+        self.entries().get(key).cloned()
+    }
+
+    fn insert(&self, key: &str, response: CachedResponse) {
+        self.entries().insert(key.to_string(), response);
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries().remove(key);
+    }
+}
+
+/// Caches `GET` responses according to their `Cache-Control` and `Expires` headers.
+///
+/// Fresh responses are answered from the cache before the request is dispatched, so they neither
+/// wait for the rate limiter nor fail while the circuit breaker is open. Stale responses carrying
+/// an `ETag` or `Last-Modified` header are revalidated with `If-None-Match` and
+/// `If-Modified-Since`, and the cached body is returned when the server answers with
+/// `304 Not Modified`. Successful calls with unsafe methods such as `POST` evict the cached
+/// response of their url.
+///
+/// Revalidations and misses pass through the cache as a [`Middleware`], so it only stores the
+/// headers set by the middleware added before it and never the credentials of the client. Clients
+/// sharing a cache should therefore be allowed to see each other's responses. Fresh responses are
+/// only looked up for caches added with
+/// [`ApiClientBuilder::with_cache`](crate::ApiClientBuilder::with_cache).
+///
+/// # Examples
+/// ```
+/// # use bc_api_client::ApiClientBuilder;
+/// # use bc_api_client::cache::HttpCache;
+/// let client = ApiClientBuilder::new("https://api.example.com")
+///     .with_cache(HttpCache::new())
+///     .build::<()>();
+/// ```
+pub struct HttpCache {
+    store: Box<dyn CacheStore>,
+}
+
+impl Default for HttpCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpCache {
+    /// Creates a cache keeping responses in memory.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_store(MemoryStore::new())
+    }
+
+    /// Creates a cache keeping responses in the provided backend.
+    ///
+    /// Pass an [`Arc`] to keep access to the backend, e.g. to clear it.
+    #[must_use]
+    pub fn with_store<S: CacheStore + 'static>(store: S) -> Self {
+        Self {
+            store: Box::new(store),
+        }
+    }
+
+    /// Returns the cached response for a `GET` request, as long as it is fresh.
+    pub(crate) fn fresh(&self, request: &Request) -> Option<Response<Vec<u8>>> {
+        let control = CacheControl::parse(request.headers());
+        if request.method() != Method::GET || control.no_store || control.no_cache {
+            return None;
+        }
+        self.store
+            .get(request.url().as_str())
+            .filter(|cached| cached.matches(request.headers()))
+            .filter(|cached| SystemTime::now() < cached.expires)
+            .map(|cached| cached.response)
+    }
+
+    /// Stores a response, or evicts the cached one if the response must not be stored.
+    fn store(&self, key: &str, headers: &HeaderMap, response: &Response<Vec<u8>>, now: SystemTime) {
+        let control = CacheControl::parse(&response.headers);
+        let vary: Option<Vec<_>> = response
+            .header_values("vary")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let name = HeaderName::try_from(name).ok()?;
+                let value = headers.get(&name).cloned();
+                Some((name, value))
+            })
+            .collect();
+        let expires = control.expires(response, now);
+        let validated = response.etag().is_some() || response.headers.contains_key(LAST_MODIFIED);
+        match vary {
+            // `Vary: *` does not parse as a header name
+            Some(vary)
+                if response.status == StatusCode::OK
+                    && !control.no_store
+                    && (expires > now || validated) =>
+            {
+                let response = CachedResponse {
+                    response: response.clone(),
+                    vary,
+                    expires,
+                };
+                self.store.insert(key, response);
+            }
+            _ => self.store.remove(key),
+        }
+    }
+}
+
+impl Middleware for HttpCache {
+    fn handle<'a>(
+        &'a self,
+        mut request: Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+        Box::pin(async move {
+            let key = request.url().as_str().to_string();
+            let control = CacheControl::parse(request.headers());
+            if next.is_streaming() || control.no_store {
+                return next.run(request).await;
+            }
+            if request.method() != Method::GET {
+                let safe = request.method().is_safe();
+                let result = next.run(request).await;
+                if !safe && result.is_ok() {
+                    self.store.remove(&key);
+                }
+                return result;
+            }
+
+            let now = SystemTime::now();
+            let cached = self
+                .store
+                .get(&key)
+                .filter(|cached| cached.matches(request.headers()));
+            // fresh responses were answered before the request was dispatched
+            if let Some(cached) = &cached {
+                let headers = request.headers_mut();
+                if let Some(etag) = cached.response.headers.get(ETAG) {
+                    headers.entry(IF_NONE_MATCH).or_insert_with(|| etag.clone());
+                }
+                if let Some(modified) = cached.response.headers.get(LAST_MODIFIED) {
+                    headers
+                        .entry(IF_MODIFIED_SINCE)
+                        .or_insert_with(|| modified.clone());
+                }
+            }
+
+            let headers = request.headers().clone();
+            let result = next.run(request).await;
+            match (result, cached) {
+                (Ok(mut response), Some(cached)) if response.status == StatusCode::NOT_MODIFIED => {
+                    // the headers of the 304 update those of the cached response, except for the
+                    // length of its own empty body
+                    response.headers.remove(CONTENT_LENGTH);
+                    let mut merged = cached.response.headers.clone();
+                    merged.extend(response.headers);
+                    let response = cached.response.with_headers(merged);
+                    self.store(&key, &headers, &response, now);
+                    Ok(response)
+                }
+                (result, _) => {
+                    if let Ok(response) = &result {
+                        self.store(&key, &headers, response, now);
+                    }
+                    result
+                }
+            }
+        })
+    }
+}
+
+/// The `Cache-Control` directives relevant to a private cache.
+#[derive(Debug, Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut control = Self::default();
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for directive in directives {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => control.no_store = true,
+                "no-cache" => control.no_cache = true,
+                "max-age" => control.max_age = value.trim().trim_matches('"').parse().ok(),
+                _ => {}
+            }
+        }
+        control
+    }
+
+    /// Returns when a response received at `now` stops being fresh.
+    fn expires(&self, response: &Response<Vec<u8>>, now: SystemTime) -> SystemTime {
+        if self.no_cache {
+            return now;
+        }
+        if let Some(max_age) = self.max_age {
+            let age = response
+                .header("age")
+                .and_then(|age| age.trim().parse().ok())
+                .unwrap_or(0);
+            return now + Duration::from_secs(max_age.saturating_sub(age));
+        }
+        // an invalid `Expires` header means the response is stale already
+        let Some(expires) = response.header("expires").map(parse_http_date) else {
+            return now;
+        };
+        // measured against the clock of the server, which may differ from ours
+        let date = response.header("date").and_then(parse_http_date);
+        match (expires, date) {
+            (Some(expires), Some(date)) => {
+                now + expires.duration_since(date).unwrap_or(Duration::ZERO)
+            }
+            (Some(expires), None) => expires,
+            (None, _) => now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClientBuilder;
+    use crate::rate_limit::RateLimiter;
+    use crate::request::Request as _;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with the status line, headers and body returned by the handler for
+    /// its lowercased head, counting the requests received.
+    async fn serve(
+        handler: fn(&str) -> (&'static str, String, String),
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let received = Arc::clone(&count);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let read = stream.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                received.fetch_add(1, Ordering::SeqCst);
+                let (status, headers, body) = handler(&head);
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nconnection: close\r\n{headers}content-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        (format!("http://{addr}"), count)
+    }
+
+    fn respond(head: &str) -> (&'static str, String, String) {
+        let target = head.split_whitespace().nth(1).unwrap_or_default();
+        let ok = |headers: &str| ("200 OK", headers.to_string(), target.to_string());
+        match target {
+            "/fresh" => ok("cache-control: max-age=60\r\n"),
+            "/revalidated" if head.contains("if-none-match: \"v1\"") => (
+                "304 Not Modified",
+                "etag: \"v1\"\r\nx-checked: yes\r\n".to_string(),
+                String::new(),
+            ),
+            "/revalidated" => ok("cache-control: no-cache\r\netag: \"v1\"\r\n"),
+            "/no-store" => ok("cache-control: no-store, max-age=60\r\n"),
+            "/uncacheable" => ok(""),
+            _ => ok("cache-control: max-age=60\r\nvary: accept\r\n"),
+        }
+    }
+
+    async fn get(client: &crate::ApiClient<()>, route: &str) -> Response<String> {
+        client.clone().get(route).request_text().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn caches_fresh_responses() {
+        let (url, count) = serve(respond).await;
+        let client = ApiClientBuilder::new(&url)
+            .with_cache(HttpCache::new())
+            .build::<()>();
+
+        assert_eq!(get(&client, "/fresh").await.body, "/fresh");
+        assert_eq!(get(&client, "/fresh").await.body, "/fresh");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // unsafe methods evict the cached response
+        client.clone().post("/fresh").request().await.unwrap();
+        assert_eq!(get(&client, "/fresh").await.body, "/fresh");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        for route in ["/no-store", "/uncacheable", "/no-store", "/uncacheable"] {
+            get(&client, route).await;
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 7);
+
+        // the request may opt out of the cache
+        let response = client
+            .clone()
+            .get("/fresh")
+            .header(CACHE_CONTROL, "no-store")
+            .request_text()
+            .await
+            .unwrap();
+        assert_eq!(response.body, "/fresh");
+        assert_eq!(count.load(Ordering::SeqCst), 8);
+    }
+
+    #[tokio::test]
+    async fn fresh_hits_skip_the_rate_limiter() {
+        let (url, count) = serve(respond).await;
+        let client = ApiClientBuilder::new(&url)
+            .with_rate_limit(RateLimiter::new(1, Duration::from_hours(1)))
+            .with_cache(HttpCache::new())
+            .build::<()>();
+
+        get(&client, "/fresh").await;
+        // a second token would only be available in an hour
+        let cached = tokio::time::timeout(Duration::from_secs(1), get(&client, "/fresh"));
+        assert_eq!(cached.await.unwrap().body, "/fresh");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn revalidates_stale_responses() {
+        let (url, count) = serve(respond).await;
+        let store = Arc::new(MemoryStore::new());
+        let client = ApiClientBuilder::new(&url)
+            .with_cache(HttpCache::with_store(Arc::clone(&store)))
+            .build::<()>();
+
+        let response = get(&client, "/revalidated").await;
+        assert_eq!(response.body, "/revalidated");
+        assert_eq!(response.header("x-checked"), None);
+
+        let response = get(&client, "/revalidated").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "/revalidated");
+        assert_eq!(response.header("x-checked"), Some("yes"));
+        assert_eq!(response.content_length(), Some(12));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        let cached = store.get(&format!("{url}/revalidated")).unwrap();
+        assert_eq!(cached.response.header("x-checked"), Some("yes"));
+    }
+
+    #[tokio::test]
+    async fn varies_on_request_headers() {
+        let (url, count) = serve(respond).await;
+        let client = ApiClientBuilder::new(&url)
+            .with_cache(HttpCache::new())
+            .build::<()>();
+        let get = |accept: &'static str| {
+            client
+                .clone()
+                .get("/varied")
+                .header("accept", accept)
+                .request_text()
+        };
+
+        get("text/plain").await.unwrap();
+        get("text/plain").await.unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        get("text/html").await.unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn freshness() {
+        let now = SystemTime::now();
+        let expires = |headers: &[(&'static str, &'static str)]| {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        HeaderName::from_static(name),
+                        HeaderValue::from_static(value),
+                    )
+                })
+                .collect();
+            let response = Response::empty()
+                .with_headers(headers)
+                .with_body(Vec::new());
+            CacheControl::parse(&response.headers)
+                .expires(&response, now)
+                .duration_since(now)
+                .unwrap_or(Duration::ZERO)
+        };
+
+        assert_eq!(
+            expires(&[("cache-control", "public, max-age=\"60\"")]),
+            Duration::from_mins(1)
+        );
+        assert_eq!(
+            expires(&[("cache-control", "max-age=60"), ("age", "45")]),
+            Duration::from_secs(15)
+        );
+        assert_eq!(
+            expires(&[("cache-control", "max-age=60, no-cache")]),
+            Duration::ZERO
+        );
+        assert_eq!(
+            expires(&[
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("expires", "Sun, 06 Nov 1994 08:59:37 GMT"),
+            ]),
+            Duration::from_mins(10)
+        );
+        assert_eq!(expires(&[("expires", "0")]), Duration::ZERO);
+        assert_eq!(expires(&[]), Duration::ZERO);
+    }
+}
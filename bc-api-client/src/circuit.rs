@@ -0,0 +1,453 @@
+use crate::{ApiError, ApiResult};
+use tokio::time::Instant;
+
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// The state of a [`CircuitBreaker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls are dispatched and their outcomes recorded.
+    Closed,
+    /// Calls fail right away until the cooldown has passed.
+    Open,
+    /// A limited number of trial calls decide whether to close the circuit again.
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half-open",
+        })
+    }
+}
+
+/// The error returned instead of dispatching a call while the circuit is open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitOpen {
+    /// The time left until trial calls are allowed, `None` while they are in flight already.
+    pub retry_in: Option<Duration>,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_in {
+            Some(retry_in) => write!(f, "circuit breaker is open, retry in {retry_in:?}"),
+            None => f.write_str("circuit breaker is half-open and waiting for trial calls"),
+        }
+    }
+}
+
+impl StdError for CircuitOpen {}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    failure_rate: f64,
+    window: usize,
+    min_calls: usize,
+    cooldown: Duration,
+    trial_calls: u32,
+}
+
+#[derive(Debug)]
+enum Phase {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        since: Instant,
+        in_flight: u32,
+        succeeded: u32,
+    },
+}
+
+#[derive(Debug)]
+struct Circuit {
+    phase: Phase,
+    /// Outcomes of the most recent calls while closed, `true` for failures.
+    outcomes: VecDeque<bool>,
+}
+
+/// Stops dispatching calls to an API that keeps failing.
+///
+/// While closed, the breaker records whether the most recent calls failed, i.e. whether they
+/// could not connect, timed out or got a server error status. Once enough calls were made and
+/// the share of failures reaches the threshold, the circuit opens and calls fail right away with
+/// [`ApiError::CircuitOpen`]. After the cooldown, a limited number of trial calls are let through:
+/// the circuit closes once they all succeed and opens again as soon as one fails.
+///
+/// Clones share the same state, so a breaker attached to an [`ApiClient`](crate::ApiClient)
+/// applies to all its clones. State changes are emitted as `tracing` events.
+///
+/// # Examples
+/// ```
+/// # use bc_api_client::ApiClientBuilder;
+/// # use bc_api_client::circuit::CircuitBreaker;
+/// # use std::time::Duration;
+/// let breaker = CircuitBreaker::new()
+///     .with_failure_rate(0.5)
+///     .with_window(20, 10)
+///     .with_cooldown(Duration::from_secs(30));
+/// let client = ApiClientBuilder::new("https://api.example.com")
+///     .with_circuit_breaker(breaker)
+///     .build::<()>();
+/// ```
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    name: Arc<str>,
+    config: Config,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker opening once half of the last 20 calls failed, provided at least 10
+    /// calls were made, and letting a single trial call through after 30 seconds.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            name: Arc::from(""),
+            config: Config {
+                failure_rate: 0.5,
+                window: 20,
+                min_calls: 10,
+                cooldown: Duration::from_secs(30),
+                trial_calls: 1,
+            },
+            circuit: Arc::new(Mutex::new(Circuit {
+                phase: Phase::Closed,
+                outcomes: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Names the breaker in the `tracing` events it emits.
+    #[must_use]
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Arc::from(name);
+        self
+    }
+
+    /// Sets the share of failed calls, between 0 and 1, at which the circuit opens.
+    ///
+    /// Only a failed call opens the circuit, so a share of 0 opens it on the first failure once
+    /// enough calls were made.
+    #[must_use]
+    pub fn with_failure_rate(mut self, failure_rate: f64) -> Self {
+        self.config.failure_rate = failure_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the number of most recent calls the failure rate is computed over, and how many
+    /// calls have to be recorded before the circuit may open.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min_calls` is zero or larger than `window`.
+    #[must_use]
+    pub fn with_window(mut self, window: usize, min_calls: usize) -> Self {
+        assert!(
+            (1..=window).contains(&min_calls),
+            "circuit breaker needs between one and `window` calls to open"
+        );
+        self.config.window = window;
+        self.config.min_calls = min_calls;
+        self
+    }
+
+    /// Sets how long the circuit stays open before trial calls are let through.
+    #[must_use]
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.config.cooldown = cooldown;
+        self
+    }
+
+    /// Sets the number of trial calls that have to succeed for the circuit to close again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `trial_calls` is zero.
+    #[must_use]
+    pub fn with_trial_calls(mut self, trial_calls: u32) -> Self {
+        assert!(
+            trial_calls > 0,
+            "circuit breaker needs at least one trial call"
+        );
+        self.config.trial_calls = trial_calls;
+        self
+    }
+
+    /// Returns the current state of the circuit.
+    #[must_use]
+    pub fn state(&self) -> CircuitState {
+        match self.lock().phase {
+            Phase::Closed => CircuitState::Closed,
+            Phase::Open { until } if Instant::now() < until => CircuitState::Open,
+            Phase::Open { .. } | Phase::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        // the circuit is never left in an inconsistent state, so poisoning can be ignored
+        self.circuit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn transition(&self, circuit: &mut Circuit, phase: Phase) {
+        let from = circuit.state();
+        circuit.phase = phase;
+        circuit.outcomes.clear();
+        let to = circuit.state();
+        match to {
+            CircuitState::Open => {
+                tracing::warn!(circuit = %self.name, %from, %to, "circuit breaker opened");
+            }
+            CircuitState::HalfOpen => {
+                tracing::info!(circuit = %self.name, %from, %to, "circuit breaker half-open");
+            }
+            CircuitState::Closed => {
+                tracing::info!(circuit = %self.name, %from, %to, "circuit breaker closed");
+            }
+        }
+    }
+
+    /// Allows a call to be dispatched, unless the circuit is open.
+    pub(crate) fn acquire(&self) -> Result<(), CircuitOpen> {
+        let now = Instant::now();
+        let mut circuit = self.lock();
+        match circuit.phase {
+            Phase::Closed => Ok(()),
+            Phase::Open { until } if now < until => Err(CircuitOpen {
+                retry_in: Some(until - now),
+            }),
+            Phase::Open { .. } => {
+                let phase = Phase::HalfOpen {
+                    since: now,
+                    in_flight: 1,
+                    succeeded: 0,
+                };
+                self.transition(&mut circuit, phase);
+                Ok(())
+            }
+            Phase::HalfOpen {
+                ref mut since,
+                ref mut in_flight,
+                succeeded,
+            } => {
+                // trial calls that never finished, e.g. because they were dropped, are given up
+                // on after another cooldown
+                if now >= *since + self.config.cooldown {
+                    *since = now;
+                    *in_flight = succeeded;
+                }
+                if *in_flight < self.config.trial_calls {
+                    *in_flight += 1;
+                    Ok(())
+                } else {
+                    Err(CircuitOpen { retry_in: None })
+                }
+            }
+        }
+    }
+
+    /// Records the outcome of a call allowed by [`CircuitBreaker::acquire`].
+    pub(crate) fn record<T, E>(&self, result: &ApiResult<T, E>) {
+        let failed = match result {
+            Ok(_) => false,
+            Err(error) => {
+                error.is_connect()
+                    || error.is_timeout()
+                    || error
+                        .status()
+                        .is_some_and(|status| status.is_server_error())
+            }
+        };
+        let now = Instant::now();
+        let mut circuit = self.lock();
+        match circuit.phase {
+            Phase::Closed => {
+                circuit.outcomes.push_back(failed);
+                if circuit.outcomes.len() > self.config.window {
+                    circuit.outcomes.pop_front();
+                }
+                let calls = circuit.outcomes.len();
+                let failures = circuit.outcomes.iter().filter(|failed| **failed).count();
+                #[allow(clippy::cast_precision_loss)] // windows are far below 2^52 calls
+                let rate = failures as f64 / calls as f64;
+                if failed && calls >= self.config.min_calls && rate >= self.config.failure_rate {
+                    let until = now + self.config.cooldown;
+                    self.transition(&mut circuit, Phase::Open { until });
+                }
+            }
+            Phase::HalfOpen { .. } if failed => {
+                let until = now + self.config.cooldown;
+                self.transition(&mut circuit, Phase::Open { until });
+            }
+            Phase::HalfOpen {
+                ref mut succeeded, ..
+            } => {
+                *succeeded += 1;
+                if *succeeded >= self.config.trial_calls {
+                    self.transition(&mut circuit, Phase::Closed);
+                }
+            }
+            // calls dispatched before the circuit opened
+            Phase::Open { .. } => {}
+        }
+    }
+}
+
+impl Circuit {
+    fn state(&self) -> CircuitState {
+        match self.phase {
+            Phase::Closed => CircuitState::Closed,
+            Phase::Open { .. } => CircuitState::Open,
+            Phase::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+impl<E> From<CircuitOpen> for ApiError<E> {
+    fn from(error: CircuitOpen) -> Self {
+        Self::CircuitOpen(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClientBuilder;
+    use crate::request::Request as _;
+    use crate::response::Response;
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn result(failed: bool) -> ApiResult<()> {
+        if failed {
+            let response = Response::empty().with_status(StatusCode::BAD_GATEWAY);
+            Err(ApiError::Status(Box::new(response.with_body(Vec::new()))))
+        } else {
+            Ok(Response::empty())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_and_recovers() {
+        let breaker = CircuitBreaker::new()
+            .with_failure_rate(0.5)
+            .with_window(4, 4)
+            .with_cooldown(Duration::from_secs(10))
+            .with_trial_calls(2);
+
+        for result in [result(true), result(false), result(false), result(true)] {
+            breaker.acquire().unwrap();
+            breaker.record(&result);
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(
+            breaker.acquire(),
+            Err(CircuitOpen {
+                retry_in: Some(Duration::from_secs(10))
+            })
+        );
+
+        // a failed trial call opens the circuit again
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.acquire().unwrap();
+        breaker.record(&result(true));
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // all trial calls have to succeed
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let clone = breaker.clone();
+        breaker.acquire().unwrap();
+        clone.acquire().unwrap();
+        assert_eq!(breaker.acquire(), Err(CircuitOpen { retry_in: None }));
+        breaker.record(&result(false));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        clone.record(&result(false));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // the window starts over once closed
+        for _ in 0..3 {
+            breaker.acquire().unwrap();
+            breaker.record(&result(true));
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn zero_failure_rate() {
+        let breaker = CircuitBreaker::new()
+            .with_failure_rate(0.0)
+            .with_window(2, 2);
+        for _ in 0..3 {
+            breaker.record(&result(false));
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record(&result(true));
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abandoned_trial_calls() {
+        let breaker = CircuitBreaker::new()
+            .with_window(1, 1)
+            .with_cooldown(Duration::from_secs(10));
+        breaker.record(&result(true));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_err());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        breaker.acquire().unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_fast_while_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicUsize::new(0));
+        let received = Arc::clone(&count);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                received.fetch_add(1, Ordering::SeqCst);
+                let response = "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\n\
+                                content-length: 0\r\n\r\n";
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        let breaker = CircuitBreaker::new().with_window(2, 2);
+        let client = ApiClientBuilder::new(&url)
+            .with_circuit_breaker(breaker.clone())
+            .build::<()>();
+        for _ in 0..2 {
+            let error = client.clone().get("/").request().await.unwrap_err();
+            assert_eq!(error.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let error = client.clone().get("/").request().await.unwrap_err();
+        assert!(error.is_circuit_open());
+        assert_eq!(error.status(), None);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::error::ErrorBody;
+use crate::request::Request;
+use crate::{ApiClient, ApiError, ApiResult};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use reqwest::Method;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use std::fmt::Display;
+
+#[doc(hidden)]
+pub use bc_utils::route as __route;
+
+/// Characters left unencoded in path parameters.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encodes a path parameter, so that it always fills a single path segment.
+#[doc(hidden)]
+pub fn __encode_segment(value: impl Display) -> String {
+    utf8_percent_encode(&value.to_string(), SEGMENT).to_string()
+}
+
+/// A typed API endpoint, usually declared with [`endpoint!`](crate::endpoint!).
+///
+/// An endpoint belongs to the API marked by [`Endpoint::Api`], so it can only be called by an
+/// [`ApiClient`] of that API.
+pub trait Endpoint {
+    /// The API marker of the clients able to call the endpoint.
+    type Api;
+    /// The query string appended to the route by [`Endpoint::route`], `()` if there is none.
+    ///
+    /// Query structs derive [`bc_query::QueryBuilder`], which only generates inherent methods, so
+    /// the type is not bounded and appending it is left to the route.
+    type Query;
+    /// The JSON body sent with the request, `()` if there is none.
+    type Body: Serialize;
+    /// The JSON body of a successful response, `()` if there is none.
+    type Response: DeserializeOwned;
+
+    const METHOD: Method;
+
+    /// Returns the filled route of the endpoint, including the query string.
+    fn route(&self) -> String;
+
+    /// Returns the query appended to the route, if any.
+    fn query(&self) -> Option<&Self::Query> {
+        None
+    }
+
+    /// Returns the body sent with the request, if any.
+    fn body(&self) -> Option<&Self::Body> {
+        None
+    }
+}
+
+/// Declares a struct implementing [`Endpoint`].
+///
+/// The fields of the struct fill the `{field}` placeholders of the route via
+/// [`bc_utils::route!`], percent-encoded so that each fills a single path segment. An optional
+/// `query` field is appended to the route through the `append_to` method generated by
+/// [`bc_query::QueryBuilder`], and an optional `body` field is sent as JSON.
+///
+/// # Examples
+/// ```
+/// # use bc_api_client::{ApiClient, ApiError, endpoint};
+/// # use bc_query::QueryBuilder;
+/// # use serde::{Deserialize, Serialize};
+/// pub struct Blog;
+///
+/// #[derive(Deserialize)]
+/// pub struct Post {
+///     pub id: u64,
+/// }
+///
+/// #[derive(Serialize)]
+/// pub struct NewPost {
+///     pub title: String,
+/// }
+///
+/// #[derive(Default, QueryBuilder)]
+/// pub struct PostQuery {
+///     pub draft: Option<bool>,
+/// }
+///
+/// endpoint! {
+///     /// Creates a post for a user.
+///     pub struct CreatePost {
+///         pub user_id: u64,
+///     }
+///     api: Blog,
+///     route: POST "/users/{user_id}/posts",
+///     query: PostQuery,
+///     body: NewPost,
+///     response: Post,
+/// }
+///
+/// # async fn example(client: ApiClient<Blog>) -> Result<(), ApiError> {
+/// let post = client
+///     .call(CreatePost {
+///         user_id: 1,
+///         query: PostQuery::new().with_draft(true),
+///         body: NewPost {
+///             title: "Hello".to_string(),
+///         },
+///     })
+///     .await?
+///     .body;
+/// println!("created post {}", post.id);
+/// # Ok(())
+/// # }
+/// ```
+#[macro_export]
+macro_rules! endpoint {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_attr:meta])* $field_vis:vis $field:ident: $field_type:ty),* $(,)?
+        }
+        api: $api:ty,
+        route: $method:ident $route:literal,
+        $(query: $query:ty,)?
+        $(body: $body:ty,)?
+        response: $response:ty $(,)?
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($(#[$field_attr])* $field_vis $field: $field_type,)*
+            $($vis query: $query,)?
+            $($vis body: $body,)?
+        }
+
+        impl $crate::endpoint::Endpoint for $name {
+            type Api = $api;
+            type Query = $crate::endpoint!(@or_unit $($query)?);
+            type Body = $crate::endpoint!(@or_unit $($body)?);
+            type Response = $response;
+
+            const METHOD: $crate::reqwest::Method = $crate::reqwest::Method::$method;
+
+            fn route(&self) -> String {
+                let Self { $($field,)* .. } = self;
+                $(let $field = $crate::endpoint::__encode_segment($field);)*
+                #[allow(unused_mut)] // without a query, mut is unused
+                let mut route = $crate::endpoint::__route!($route $(, $field)*);
+                $(
+                    let query: &$query = &self.query;
+                    query.append_to(&mut route);
+                )?
+                route
+            }
+
+            $(
+                fn query(&self) -> Option<&$query> {
+                    Some(&self.query)
+                }
+            )?
+
+            $(
+                fn body(&self) -> Option<&$body> {
+                    Some(&self.body)
+                }
+            )?
+        }
+    };
+    (@or_unit $type:ty) => { $type };
+    (@or_unit) => { () };
+}
+
+impl<T, E: ErrorBody> ApiClient<T, E> {
+    /// Calls a typed endpoint of the API and decodes its JSON response.
+    ///
+    /// An empty response body, e.g. of a `204 No Content` response, is decoded like a JSON
+    /// `null`, so endpoints without a response use `()` or an [`Option`].
+    ///
+    /// # Errors
+    ///
+    /// Errors if the request fails, the API responds with an error status or the response body
+    /// does not match [`Endpoint::Response`].
+    pub async fn call<P: Endpoint<Api = T>>(self, endpoint: P) -> ApiResult<P::Response, E> {
+        let mut request = self.request(P::METHOD, &endpoint.route());
+        if let Some(body) = endpoint.body() {
+            request = request.json(body);
+        }
+        let mut response = request.request().await?;
+        if response.body.is_empty() {
+            response.body = b"null".to_vec();
+        }
+        response.try_into_json().map_err(ApiError::with_error_body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bc_query::QueryBuilder;
+    use serde::Deserialize;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    struct Blog;
+
+    #[derive(Debug, Deserialize, PartialEq, Eq)]
+    struct Echo {
+        target: String,
+        method: String,
+        body: String,
+    }
+
+    #[derive(Serialize)]
+    struct NewPost {
+        title: &'static str,
+    }
+
+    #[derive(Default, QueryBuilder)]
+    struct PostQuery {
+        limit: Option<u32>,
+        draft: Option<bool>,
+    }
+
+    endpoint! {
+        /// Fetches a single post.
+        #[derive(Clone, Debug)]
+        struct GetPost {
+            user_id: u64,
+            post_id: u64,
+        }
+        api: Blog,
+        route: GET "/users/{user_id}/posts/{post_id}",
+        response: Echo,
+    }
+
+    endpoint! {
+        struct DeleteTag {
+            name: String,
+        }
+        api: Blog,
+        route: DELETE "/tags/{name}",
+        response: (),
+    }
+
+    endpoint! {
+        struct CreatePost {
+            user_id: u64,
+        }
+        api: Blog,
+        route: POST "/users/{user_id}/posts",
+        query: PostQuery,
+        body: NewPost,
+        response: Echo,
+    }
+
+    /// Answers every request with its method, target and body as JSON, and deletions with no
+    /// content.
+    async fn serve_echo() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = String::new();
+                let mut buf = [0u8; 4096];
+                // the body may arrive separately from the head
+                let (head, body) = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buf[..read]));
+                    if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(str::to_string)
+                            })
+                            .map_or(0, |length| length.trim().parse().unwrap());
+                        if body.len() >= length {
+                            break (head, body);
+                        }
+                    }
+                };
+                let mut line = head.split_whitespace();
+                let echo = serde_json::json!({
+                    "method": line.next(),
+                    "target": line.next(),
+                    "body": body,
+                })
+                .to_string();
+                let response = if head.starts_with("DELETE") {
+                    "HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n".to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{echo}",
+                        echo.len()
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn routes() {
+        let endpoint = GetPost {
+            user_id: 1,
+            post_id: 22,
+        };
+        assert_eq!(endpoint.route(), "/users/1/posts/22");
+        assert!(endpoint.query().is_none());
+        assert!(endpoint.body().is_none());
+        assert_eq!(GetPost::METHOD, Method::GET);
+
+        let endpoint = CreatePost {
+            user_id: 7,
+            query: PostQuery::new().with_limit(5).with_draft(true),
+            body: NewPost { title: "hello" },
+        };
+        assert_eq!(endpoint.route(), "/users/7/posts?limit=5&draft=true");
+        assert_eq!(endpoint.query().unwrap().limit, Some(5));
+        assert_eq!(endpoint.body().unwrap().title, "hello");
+
+        let endpoint = DeleteTag {
+            name: "a/b c?d".to_string(),
+        };
+        assert_eq!(endpoint.route(), "/tags/a%2Fb%20c%3Fd");
+    }
+
+    #[tokio::test]
+    async fn call() {
+        let url = serve_echo().await;
+        let client = ApiClient::<Blog>::new(reqwest::Client::new(), &url, ());
+
+        let response = client
+            .clone()
+            .call(GetPost {
+                user_id: 1,
+                post_id: 22,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            response.body,
+            Echo {
+                target: "/users/1/posts/22".to_string(),
+                method: "GET".to_string(),
+                body: String::new(),
+            }
+        );
+
+        let response = client
+            .clone()
+            .call(CreatePost {
+                user_id: 7,
+                query: PostQuery::new().with_draft(false),
+                body: NewPost { title: "hello" },
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            response.body,
+            Echo {
+                target: "/users/7/posts?draft=false".to_string(),
+                method: "POST".to_string(),
+                body: r#"{"title":"hello"}"#.to_string(),
+            }
+        );
+
+        let response = client
+            .call(DeleteTag {
+                name: "rust".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.status, reqwest::StatusCode::NO_CONTENT);
+    }
+}
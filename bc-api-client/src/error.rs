@@ -0,0 +1,298 @@
+use crate::circuit::CircuitOpen;
+use crate::response::Response;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Maximum number of body bytes quoted by a [`DecodeError`].
+const SNIPPET_LEN: usize = 256;
+
+/// A type that error response bodies of an API are decoded into.
+///
+/// Implemented for every type that can be deserialized from JSON, while [`Untyped`] is used when
+/// an API does not declare an error body type.
+pub trait ErrorBody: Sized {
+    /// Attempts to decode an error response body, returning `None` if it does not parse.
+    fn decode(body: &[u8]) -> Option<Self>;
+}
+
+impl<E: DeserializeOwned> ErrorBody for E {
+    fn decode(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+}
+
+/// Error body type of APIs that do not declare one.
+///
+/// It cannot be instantiated, so error responses are always kept as raw
+/// [`ApiError::Status`] responses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Untyped {}
+
+impl ErrorBody for Untyped {
+    fn decode(_body: &[u8]) -> Option<Self> {
+        None
+    }
+}
+
+/// Errors that can occur while dispatching an API call and processing its response.
+///
+/// The error body type `E` is declared by the API, see [`ErrorBody`].
+#[derive(Debug)]
+pub enum ApiError<E = Untyped> {
+    /// The request or reading the response body timed out.
+    Timeout(reqwest::Error),
+    /// A connection to the server could not be established.
+    Connect(reqwest::Error),
+    /// The TLS handshake with the server failed.
+    Tls(reqwest::Error),
+    /// Any other transport error, e.g. an invalid url, a redirect loop or an interrupted body.
+    Request(reqwest::Error),
+    /// The server responded with a client or server error status.
+    ///
+    /// The response keeps the headers and the raw body as sent by the server. Error responses
+    /// whose body does not parse into the declared error body type end up here as well.
+    Status(Box<Response<Vec<u8>>>),
+    /// The server responded with a client or server error status and the body was decoded into
+    /// the declared error body type.
+    Typed(Box<Response<E>>),
+    /// The response body could not be deserialized into the expected type.
+    Decode(DecodeError),
+    /// The call was not dispatched since the circuit breaker of the client is open, see
+    /// [`CircuitBreaker`](crate::circuit::CircuitBreaker).
+    CircuitOpen(CircuitOpen),
+}
+
+impl ApiError {
+    /// Attempts to decode the body of an error response into the provided error body type.
+    ///
+    /// Responses whose body does not parse are kept as they are, and so are all other errors.
+    #[must_use]
+    pub fn with_error_body<E: ErrorBody>(self) -> ApiError<E> {
+        match self {
+            Self::Timeout(error) => ApiError::Timeout(error),
+            Self::Connect(error) => ApiError::Connect(error),
+            Self::Tls(error) => ApiError::Tls(error),
+            Self::Request(error) => ApiError::Request(error),
+            Self::Status(response) => match E::decode(&response.body) {
+                Some(body) => ApiError::Typed(Box::new(response.with_body(body))),
+                None => ApiError::Status(response),
+            },
+            Self::Typed(response) => match response.body {},
+            Self::Decode(error) => ApiError::Decode(error),
+            Self::CircuitOpen(error) => ApiError::CircuitOpen(error),
+        }
+    }
+}
+
+impl<E> ApiError<E> {
+    /// Returns the status code of the response, if the server responded at all.
+    #[must_use]
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Timeout(error)
+            | Self::Connect(error)
+            | Self::Tls(error)
+            | Self::Request(error) => error.status(),
+            Self::Status(response) => Some(response.status),
+            Self::Typed(response) => Some(response.status),
+            Self::Decode(error) => Some(error.status),
+            Self::CircuitOpen(_) => None,
+        }
+    }
+
+    /// Returns the decoded error body if the server responded with an error status.
+    #[must_use]
+    pub fn error_body(&self) -> Option<&E> {
+        match self {
+            Self::Typed(response) => Some(&response.body),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw error response if the server responded with an error status whose body
+    /// was not decoded.
+    #[must_use]
+    pub fn response(&self) -> Option<&Response<Vec<u8>>> {
+        match self {
+            Self::Status(response) => Some(response),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Timeout(_))
+    }
+
+    #[must_use]
+    pub fn is_connect(&self) -> bool {
+        matches!(self, Self::Connect(_) | Self::Tls(_))
+    }
+
+    #[must_use]
+    pub fn is_status(&self) -> bool {
+        matches!(self, Self::Status(_) | Self::Typed(_))
+    }
+
+    #[must_use]
+    pub fn is_decode(&self) -> bool {
+        matches!(self, Self::Decode(_))
+    }
+
+    #[must_use]
+    pub fn is_circuit_open(&self) -> bool {
+        matches!(self, Self::CircuitOpen(_))
+    }
+}
+
+impl<E> From<reqwest::Error> for ApiError<E> {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Self::Timeout(error)
+        } else if is_tls_error(&error) {
+            Self::Tls(error)
+        } else if error.is_connect() {
+            Self::Connect(error)
+        } else {
+            Self::Request(error)
+        }
+    }
+}
+
+impl<E> From<DecodeError> for ApiError<E> {
+    fn from(error: DecodeError) -> Self {
+        Self::Decode(error)
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for ApiError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(error) => write!(f, "request timed out: {error}"),
+            Self::Connect(error) => write!(f, "failed to connect: {error}"),
+            Self::Tls(error) => write!(f, "TLS handshake failed: {error}"),
+            Self::Request(error) => write!(f, "request failed: {error}"),
+            Self::Status(response) => write!(
+                f,
+                "server responded with {}: {}",
+                response.status,
+                snippet(&response.body)
+            ),
+            Self::Typed(response) => write!(
+                f,
+                "server responded with {}: {:?}",
+                response.status, response.body
+            ),
+            Self::Decode(error) => error.fmt(f),
+            Self::CircuitOpen(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<E: fmt::Debug> StdError for ApiError<E> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Timeout(error)
+            | Self::Connect(error)
+            | Self::Tls(error)
+            | Self::Request(error) => Some(error),
+            Self::Status(_) | Self::Typed(_) => None,
+            Self::Decode(error) => Some(error),
+            Self::CircuitOpen(error) => Some(error),
+        }
+    }
+}
+
+/// A response body that could not be deserialized.
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Status of the response whose body failed to decode.
+    pub status: StatusCode,
+    /// Path to the offending value within the body, e.g. `data[0].id`.
+    pub path: String,
+    /// The leading part of the body, lossily converted to UTF-8.
+    pub snippet: String,
+    /// The error of the format specific decoder.
+    pub source: Box<dyn StdError + Send + Sync>,
+}
+
+impl DecodeError {
+    pub(crate) fn new(
+        status: StatusCode,
+        path: String,
+        body: &[u8],
+        source: impl Into<Box<dyn StdError + Send + Sync>>,
+    ) -> Self {
+        Self {
+            status,
+            path,
+            snippet: snippet(body),
+            source: source.into(),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to decode response body at '{}': {} (body: {})",
+            self.path, self.source, self.snippet
+        )
+    }
+}
+
+impl StdError for DecodeError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+fn snippet(body: &[u8]) -> String {
+    let end = body.len().min(SNIPPET_LEN);
+    let mut snippet = String::from_utf8_lossy(&body[..end]).to_string();
+    if body.len() > SNIPPET_LEN {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+/// Looks for an error of the enabled TLS backends in the source chain.
+///
+/// I/O errors wrapping another error do not report it as their source, so they are unwrapped
+/// explicitly.
+fn is_tls_error(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if is_tls_backend_error(error) {
+            return true;
+        }
+        source = match error
+            .downcast_ref::<std::io::Error>()
+            .and_then(|error| error.get_ref())
+        {
+            Some(inner) => Some(inner as &(dyn StdError + 'static)),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+#[cfg_attr(
+    not(any(feature = "native-tls", feature = "rustls")),
+    allow(unused_variables)
+)]
+fn is_tls_backend_error(error: &(dyn StdError + 'static)) -> bool {
+    #[cfg(feature = "native-tls")]
+    if error.is::<native_tls::Error>() {
+        return true;
+    }
+    #[cfg(feature = "rustls")]
+    if error.is::<rustls::Error>() {
+        return true;
+    }
+    false
+}
@@ -5,24 +5,53 @@
 
 /// Various authentication method implementations for interacting with APIs.
 pub mod auth;
+pub mod cache;
+pub mod circuit;
+pub mod endpoint;
+pub mod error;
+pub mod middleware;
+/// A local mock server for testing API clients offline.
+#[cfg(feature = "test-utils")]
+pub mod mock;
+pub mod multipart;
+pub mod paginate;
+pub mod rate_limit;
 pub mod request;
 pub mod response;
+pub mod retry;
+pub mod sse;
+pub mod stream;
+/// Record-and-replay cassettes for testing API clients offline.
+#[cfg(feature = "test-utils")]
+pub mod vcr;
 
-use auth::Auth;
+use cache::HttpCache;
+use circuit::CircuitBreaker;
+pub use error::ApiError;
+use error::Untyped;
+use middleware::Middleware;
+use rate_limit::RateLimiter;
+use request::ApiRequest;
+use retry::RetryPolicy;
 
 pub use reqwest;
-use reqwest::{Client, Method, RequestBuilder};
+use reqwest::{Client, Method};
 
 use std::marker::PhantomData;
 use std::sync::Arc;
 
-pub type ApiResult<T> = Result<response::Response<T>, response::Response<String>>;
+pub type ApiResult<T, E = Untyped> = Result<response::Response<T>, ApiError<E>>;
 
 #[must_use]
 pub struct ApiClientBuilder<'a> {
     client: Client,
     base_url: &'a str,
-    auth: Box<dyn Auth + Send + Sync>,
+    auth: Box<dyn Middleware>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    retry: Option<RetryPolicy>,
+    rate_limit: Option<RateLimiter>,
+    circuit_breaker: Option<CircuitBreaker>,
+    cache: Option<Arc<HttpCache>>,
 }
 
 impl<'a> ApiClientBuilder<'a> {
@@ -31,6 +60,11 @@ impl<'a> ApiClientBuilder<'a> {
             client: Client::new(),
             base_url,
             auth: Box::new(()),
+            middleware: Vec::new(),
+            retry: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            cache: None,
         }
     }
 
@@ -39,27 +73,78 @@ impl<'a> ApiClientBuilder<'a> {
         self
     }
 
-    pub fn with_auth<A: Auth + Send + Sync + 'static>(self, auth: A) -> Self {
+    pub fn with_auth<A: Middleware + 'static>(self, auth: A) -> Self {
         Self {
-            client: self.client,
-            base_url: self.base_url,
             auth: Box::new(auth),
+            ..self
         }
     }
 
+    /// Appends a middleware to the chain every call passes through, see [`Middleware`].
+    ///
+    /// Middleware runs in the order it was added. The authentication method always runs last,
+    /// and retries and rate limiting wrap the whole chain.
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Caches `GET` responses, see [`HttpCache`].
+    ///
+    /// Fresh responses are returned before the circuit breaker and the rate limiter are consulted.
+    /// For revalidations and misses, the cache is appended to the middleware chain, so it sees
+    /// the changes made by the middleware added before it.
+    pub fn with_cache(mut self, cache: HttpCache) -> Self {
+        let cache = Arc::new(cache);
+        self.middleware
+            .push(Arc::clone(&cache) as Arc<dyn Middleware>);
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Retries failed calls according to the provided policy, see [`RetryPolicy`].
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Limits the rate of calls dispatched by the client and all its clones, see
+    /// [`RateLimiter`].
+    pub fn with_rate_limit(mut self, rate_limit: RateLimiter) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Stops dispatching calls while the API keeps failing, see [`CircuitBreaker`].
+    ///
+    /// The breaker is shared by all clones of the client.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
     #[must_use]
     pub fn build<T>(self) -> ApiClient<T> {
         ApiClient {
             client: self.client,
             base_url: Arc::from(self.base_url),
             auth: Arc::from(self.auth),
+            middleware: Arc::from(self.middleware),
+            retry: self.retry,
+            rate_limit: self.rate_limit,
+            circuit_breaker: self.circuit_breaker,
+            cache: self.cache,
             _api: PhantomData,
+            _error: PhantomData,
         }
     }
 }
 
-#[derive(Clone)]
-pub struct ApiClient<T> {
+/// A client for calling the API identified by the marker type `T`.
+///
+/// Error responses are decoded into the error body type `E`, see
+/// [`ApiClient::with_error_body`].
+pub struct ApiClient<T, E = Untyped> {
     /// The client to dispatch calls with.
     pub client: Client,
     /// The base url where the API can be reached.
@@ -68,8 +153,38 @@ pub struct ApiClient<T> {
     ///
     /// Once initialized, it can still be overridden if the same API needs a different
     /// authentication method.
-    pub auth: Arc<dyn Auth + Send + Sync>,
+    pub auth: Arc<dyn Middleware>,
+    /// The middleware every call passes through before the authentication method is applied.
+    pub middleware: Arc<[Arc<dyn Middleware>]>,
+    /// The policy failed calls are retried with, if any.
+    pub retry: Option<RetryPolicy>,
+    /// The limiter shared by all clones of the client, if any.
+    pub rate_limit: Option<RateLimiter>,
+    /// The circuit breaker shared by all clones of the client, if any.
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// The cache fresh responses are looked up in before dispatching a call, if any.
+    ///
+    /// The same cache is part of the middleware chain.
+    pub cache: Option<Arc<HttpCache>>,
     _api: PhantomData<T>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<T, E> Clone for ApiClient<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            base_url: Arc::clone(&self.base_url),
+            auth: Arc::clone(&self.auth),
+            middleware: Arc::clone(&self.middleware),
+            retry: self.retry,
+            rate_limit: self.rate_limit.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            cache: self.cache.clone(),
+            _api: PhantomData,
+            _error: PhantomData,
+        }
+    }
 }
 
 impl<T> ApiClient<T> {
@@ -81,16 +196,58 @@ impl<T> ApiClient<T> {
     /// # type MyApi = ();
     /// // Instead of passing a new client, it is recommended to clone an existing
     /// // reqwest::Client.
-    /// // Note, that () implements the Auth trait, and it means that no
-    /// // authentication method is applied to the dispatched requests.
+    /// // Note, that () implements the Auth trait, and therefore the Middleware
+    /// // trait expected here, and it means that no authentication method is
+    /// // applied to the dispatched requests.
     /// let client = ApiClient::<MyApi>::new(reqwest::Client::new(), "example.com", ());
     /// ```
-    pub fn new<A: Auth + Send + Sync + 'static>(client: Client, base_url: &str, auth: A) -> Self {
+    pub fn new<A: Middleware + 'static>(client: Client, base_url: &str, auth: A) -> Self {
         Self {
             client,
             base_url: Arc::from(base_url),
             auth: Arc::new(auth),
+            middleware: Arc::new([]),
+            retry: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            cache: None,
+            _api: PhantomData,
+            _error: PhantomData,
+        }
+    }
+}
+
+impl<T, E> ApiClient<T, E> {
+    /// Declares the type that error response bodies of the API are decoded into.
+    ///
+    /// Error responses whose body parses into `F` are returned as [`ApiError::Typed`], the rest
+    /// are kept as raw [`ApiError::Status`] responses.
+    ///
+    /// # Examples
+    /// ```
+    /// # use bc_api_client::ApiClient;
+    /// # type MyApi = ();
+    /// #[derive(serde::Deserialize)]
+    /// struct MyError {
+    ///     message: String,
+    /// }
+    ///
+    /// let client = ApiClient::<MyApi>::new(reqwest::Client::new(), "example.com", ())
+    ///     .with_error_body::<MyError>();
+    /// ```
+    #[must_use]
+    pub fn with_error_body<F>(self) -> ApiClient<T, F> {
+        ApiClient {
+            client: self.client,
+            base_url: self.base_url,
+            auth: self.auth,
+            middleware: self.middleware,
+            retry: self.retry,
+            rate_limit: self.rate_limit,
+            circuit_breaker: self.circuit_breaker,
+            cache: self.cache,
             _api: PhantomData,
+            _error: PhantomData,
         }
     }
 
@@ -110,42 +267,48 @@ impl<T> ApiClient<T> {
     /// let auth_client = client.with_auth_cloned(auth);
     /// ```
     #[must_use]
-    pub fn with_auth_cloned<A: Auth + Send + Sync + 'static>(&self, auth: A) -> Self {
+    pub fn with_auth_cloned<A: Middleware + 'static>(&self, auth: A) -> Self {
         Self {
             client: self.client.clone(), // cheap due to Arc (and recommended)
             base_url: Arc::clone(&self.base_url),
             auth: Arc::new(auth),
+            middleware: Arc::clone(&self.middleware),
+            retry: self.retry,
+            rate_limit: self.rate_limit.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            cache: self.cache.clone(),
             _api: PhantomData,
+            _error: PhantomData,
         }
     }
 
-    pub fn request(self, method: Method, route: &str) -> RequestBuilder {
+    pub fn request(self, method: Method, route: &str) -> ApiRequest<E> {
         let url = format!("{}{}", self.base_url, route);
         let request = self.client.request(method, url);
-        self.auth.attach(request)
+        ApiRequest::new(request, &self, route)
     }
 
-    pub fn get(self, route: &str) -> RequestBuilder {
+    pub fn get(self, route: &str) -> ApiRequest<E> {
         self.request(Method::GET, route)
     }
 
-    pub fn post(self, route: &str) -> RequestBuilder {
+    pub fn post(self, route: &str) -> ApiRequest<E> {
         self.request(Method::POST, route)
     }
 
-    pub fn put(self, route: &str) -> RequestBuilder {
+    pub fn put(self, route: &str) -> ApiRequest<E> {
         self.request(Method::PUT, route)
     }
 
-    pub fn patch(self, route: &str) -> RequestBuilder {
+    pub fn patch(self, route: &str) -> ApiRequest<E> {
         self.request(Method::PATCH, route)
     }
 
-    pub fn delete(self, route: &str) -> RequestBuilder {
+    pub fn delete(self, route: &str) -> ApiRequest<E> {
         self.request(Method::DELETE, route)
     }
 
-    pub fn head(self, route: &str) -> RequestBuilder {
+    pub fn head(self, route: &str) -> ApiRequest<E> {
         self.request(Method::HEAD, route)
     }
 }
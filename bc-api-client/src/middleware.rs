@@ -0,0 +1,379 @@
+use crate::ApiResult;
+use crate::auth::Auth;
+use crate::request::{read_response, response_head};
+use reqwest::{Client, Request, RequestBuilder};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A boxed future that can be sent across threads.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Intercepts the API calls dispatched by an [`ApiClient`](crate::ApiClient).
+///
+/// A middleware receives the outgoing request together with the rest of the chain. It can modify
+/// the request before passing it on via [`Next::run`], inspect or transform the response returned
+/// by the chain, or answer the request on its own without calling the chain at all.
+///
+/// Every [`Auth`] implementation is a middleware as well, attaching itself to the request before
+/// passing it on. Tuples of up to four middleware run all of them in order, e.g. an
+/// [`ApiKey`](crate::auth::ApiKey) along with [`OAuth2`](crate::auth::OAuth2).
+///
+/// # Examples
+/// ```
+/// # use bc_api_client::ApiResult;
+/// # use bc_api_client::middleware::{BoxFuture, Middleware, Next};
+/// struct Logger;
+///
+/// impl Middleware for Logger {
+///     fn handle<'a>(
+///         &'a self,
+///         request: reqwest::Request,
+///         next: Next<'a>,
+///     ) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+///         Box::pin(async move {
+///             let url = request.url().clone();
+///             let result = next.run(request).await;
+///             println!("{url}: {:?}", result.as_ref().map(|response| response.status));
+///             result
+///         })
+///     }
+/// }
+/// ```
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, ApiResult<Vec<u8>>>;
+}
+
+impl<A: Auth + Send + Sync + ?Sized> Middleware for A {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+        let request = RequestBuilder::from_parts(next.client.clone(), request);
+        let request = self.attach(request).build();
+        Box::pin(async move { next.run(request?).await })
+    }
+}
+
+macro_rules! composite {
+    ($first:ident, $($rest:ident),+) => {
+        impl<$first: Middleware, $($rest: Middleware),+> Middleware for ($first, $($rest,)+) {
+            #[allow(non_snake_case)]
+            fn handle<'a>(
+                &'a self,
+                request: Request,
+                next: Next<'a>,
+            ) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+                let ($first, $($rest,)+) = self;
+                Box::pin(async move {
+                    let rest = [$($rest as &dyn Middleware),+];
+                    $first.handle(request, next.nest(&rest)).await
+                })
+            }
+        }
+    };
+}
+
+composite!(A, B);
+composite!(A, B, C);
+composite!(A, B, C, D);
+
+/// The rest of a middleware chain.
+///
+/// Client middleware runs in the order it was added, followed by the authentication method of
+/// the client, so that signatures cover all changes made to the request. Copies of `Next` run
+/// the same rest of the chain, so a middleware can dispatch a request multiple times.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    client: &'a Client,
+    /// The remaining members of a composite middleware, which run before `outer`.
+    nested: &'a [&'a dyn Middleware],
+    /// The chain that continues once the composite middleware has run.
+    outer: Option<&'a Next<'a>>,
+    middleware: &'a [Arc<dyn Middleware>],
+    auth: Option<&'a dyn Middleware>,
+    streamed: Option<&'a Mutex<Option<reqwest::Response>>>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        client: &'a Client,
+        middleware: &'a [Arc<dyn Middleware>],
+        auth: &'a dyn Middleware,
+    ) -> Self {
+        Self {
+            client,
+            nested: &[],
+            outer: None,
+            middleware,
+            auth: Some(auth),
+            streamed: None,
+        }
+    }
+
+    /// Creates a chain that stores successful responses in `streamed` instead of reading their
+    /// body, for the caller to stream it once the chain returns.
+    pub(crate) fn streaming(
+        client: &'a Client,
+        middleware: &'a [Arc<dyn Middleware>],
+        auth: &'a dyn Middleware,
+        streamed: &'a Mutex<Option<reqwest::Response>>,
+    ) -> Self {
+        Self {
+            streamed: Some(streamed),
+            ..Self::new(client, middleware, auth)
+        }
+    }
+
+    /// Runs the members of a composite middleware before the rest of this chain.
+    fn nest<'b>(&'b self, nested: &'b [&'b dyn Middleware]) -> Next<'b> {
+        Next {
+            client: self.client,
+            nested,
+            outer: Some(self),
+            middleware: &[],
+            auth: None,
+            streamed: self.streamed,
+        }
+    }
+
+    /// Returns the client the request is eventually dispatched with.
+    #[must_use]
+    pub fn client(&self) -> &Client {
+        self.client
+    }
+
+    /// Returns whether the call streams its response body, see
+    /// [`Request::request_stream`](crate::request::Request::request_stream).
+    ///
+    /// Streamed calls are dispatched like any other, and the chain sees the real status and
+    /// headers of the response. Successful responses carry an empty body though, since their body
+    /// is streamed to the caller once the chain returns, along with the status and headers
+    /// returned by the chain. Error responses are read as a whole. Responses returned by a
+    /// middleware on its own are streamed as they are.
+    #[must_use]
+    pub fn is_streaming(&self) -> bool {
+        self.streamed.is_some()
+    }
+
+    /// Reads the body of a response the rest of the chain stored for streaming into `result`,
+    /// so that a middleware can inspect it. The body is handed to the caller as a whole then.
+    #[cfg(feature = "test-utils")]
+    pub(crate) async fn read_streamed(&self, result: ApiResult<Vec<u8>>) -> ApiResult<Vec<u8>> {
+        let response = self.streamed.and_then(|streamed| {
+            streamed
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take()
+        });
+        match (response, result) {
+            (Some(response), Ok(head)) => {
+                let body = response.bytes().await?;
+                Ok(head.with_body(body.to_vec()))
+            }
+            (_, result) => result,
+        }
+    }
+
+    /// Passes the request on to the rest of the chain and dispatches it.
+    pub fn run(mut self, request: Request) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+        if let [middleware, rest @ ..] = self.nested {
+            self.nested = rest;
+            return middleware.handle(request, self);
+        }
+        if let Some(outer) = self.outer {
+            return outer.run(request);
+        }
+        if let [middleware, rest @ ..] = self.middleware {
+            self.middleware = rest;
+            return middleware.handle(request, self);
+        }
+        if let Some(auth) = self.auth.take() {
+            return auth.handle(request, self);
+        }
+        Box::pin(async move {
+            let response = self.client.execute(request).await?;
+            let head = response_head(&response);
+            match self.streamed {
+                Some(streamed) if !head.is_error() => {
+                    *streamed.lock().unwrap_or_else(PoisonError::into_inner) = Some(response);
+                    Ok(head.with_body(Vec::new()))
+                }
+                _ => read_response(response).await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClientBuilder;
+    use crate::auth::{ApiKey, Bearer, SigV4};
+    use crate::request::Request as _;
+    use crate::response::Response;
+    use futures_util::{StreamExt, TryStreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every connection with the head of the request it received.
+    async fn serve_echo() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let read = stream.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{head}",
+                    head.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    /// Records its name before and after the rest of the chain and tags the request.
+    struct Record {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Record {
+        fn handle<'a>(
+            &'a self,
+            mut request: Request,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+            Box::pin(async move {
+                self.log.lock().unwrap().push(format!("> {}", self.name));
+                request
+                    .headers_mut()
+                    .append("x-chain", self.name.parse().unwrap());
+                let result = next.run(request).await;
+                self.log.lock().unwrap().push(format!("< {}", self.name));
+                result
+            })
+        }
+    }
+
+    /// Copies the length of the response passing back through the chain into another header.
+    struct Measure;
+
+    impl Middleware for Measure {
+        fn handle<'a>(
+            &'a self,
+            request: Request,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+            Box::pin(async move {
+                let mut result = next.run(request).await;
+                if let Ok(response) = &mut result
+                    && let Some(length) = response.headers.get("content-length").cloned()
+                {
+                    response.headers.insert("x-measured", length);
+                }
+                result
+            })
+        }
+    }
+
+    /// Answers every request itself.
+    struct Canned;
+
+    impl Middleware for Canned {
+        fn handle<'a>(&'a self, _: Request, _: Next<'a>) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+            Box::pin(async { Ok(Response::empty().with_body(b"canned".to_vec())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_in_order() {
+        let url = serve_echo().await;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = ApiClientBuilder::new(&url)
+            .with_auth(Bearer::new("secret"))
+            .with_middleware(Record {
+                name: "outer",
+                log: Arc::clone(&log),
+            })
+            .with_middleware(Record {
+                name: "inner",
+                log: Arc::clone(&log),
+            })
+            .build::<()>();
+
+        let head = client.get("/").request_text().await.unwrap().body;
+        assert!(head.contains("x-chain: outer\r\nx-chain: inner"));
+        assert!(head.contains("authorization: bearer secret"));
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["> outer", "> inner", "< inner", "< outer"]
+        );
+    }
+
+    #[tokio::test]
+    async fn short_circuits() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        // nothing listens on this url, so the call only succeeds if it never leaves the chain
+        let client = ApiClientBuilder::new("http://127.0.0.1:9")
+            .with_middleware(Record {
+                name: "outer",
+                log: Arc::clone(&log),
+            })
+            .with_middleware(Canned)
+            .build::<()>();
+
+        let response = client.clone().get("/").request_text().await.unwrap();
+        assert_eq!(response.body, "canned");
+        assert_eq!(*log.lock().unwrap(), ["> outer", "< outer"]);
+
+        // streamed calls are answered by the chain as well
+        let mut body = client.get("/").request_stream().await.unwrap().body;
+        assert_eq!(body.next().await.unwrap().unwrap(), "canned");
+        assert!(body.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn streamed_responses_pass_through() {
+        let url = serve_echo().await;
+        let client = ApiClientBuilder::new(&url)
+            .with_middleware(Measure)
+            .build::<()>();
+
+        let response = client.get("/").request_stream().await.unwrap();
+        assert_eq!(
+            response.headers.get("x-measured"),
+            response.headers.get("content-length")
+        );
+        let body = response
+            .body
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .concat();
+        assert!(body.starts_with(b"get / http/1.1"));
+    }
+
+    #[tokio::test]
+    async fn composite_auth() {
+        let url = serve_echo().await;
+        let auth = (
+            ApiKey::header("x-api-key", "secret"),
+            SigV4::new("AKID", "secret", "us-east-1", "execute-api"),
+        );
+        let client = ApiClientBuilder::new(&url).with_auth(auth).build::<()>();
+
+        let head = client.get("/").request_text().await.unwrap().body;
+        assert!(head.contains("x-api-key: secret"));
+        // the key is attached first, so it is covered by the signature
+        let authorization = head
+            .lines()
+            .find_map(|line| line.strip_prefix("authorization: "))
+            .unwrap();
+        assert!(authorization.starts_with("aws4-hmac-sha256 credential=akid/"));
+        assert!(authorization.contains("x-api-key"));
+    }
+}
@@ -0,0 +1,533 @@
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::AbortHandle;
+
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A request received by a [`MockServer`].
+#[derive(Clone, Debug)]
+pub struct CapturedRequest {
+    pub method: Method,
+    pub path: String,
+    /// The query string without the leading `?`, if any.
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl CapturedRequest {
+    /// Returns the value of a header, if it is present and valid UTF-8.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    /// Parses the body as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the body is not valid JSON for `T`.
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+/// The canned response of a [`Mock`].
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    /// Creates an empty response with the provided status.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the status code is not within 100 to 999.
+    #[must_use]
+    pub fn new(status: u16) -> Self {
+        Self {
+            status: StatusCode::from_u16(status).expect("invalid status code"),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header to the response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the header name or value is invalid.
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(header_name(name), header_value(value));
+        self
+    }
+
+    #[must_use]
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets a JSON body along with its content type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the body fails to serialize.
+    #[must_use]
+    pub fn with_json<B: Serialize>(mut self, body: &B) -> Self {
+        self.body = serde_json::to_vec(body).expect("failed to serialize mock body");
+        self.headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+enum BodyMatcher {
+    Exact(Vec<u8>),
+    Json(Value),
+}
+
+/// Answers the requests matching the method, route and all further conditions with a canned
+/// response, `200 OK` without a body by default.
+///
+/// Segments of the route written as `{name}` match any single path segment.
+#[derive(Clone, Debug)]
+pub struct Mock {
+    method: Method,
+    route: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    query: Vec<(String, String)>,
+    body: Option<BodyMatcher>,
+    response: MockResponse,
+}
+
+impl Mock {
+    #[must_use]
+    pub fn new(method: Method, route: &str) -> Self {
+        Self {
+            method,
+            route: route.to_string(),
+            headers: Vec::new(),
+            query: Vec::new(),
+            body: None,
+            response: MockResponse::new(200),
+        }
+    }
+
+    /// Only matches requests carrying the header with exactly the provided value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the header name or value is invalid.
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((header_name(name), header_value(value)));
+        self
+    }
+
+    /// Only matches requests carrying the query parameter with exactly the provided value.
+    #[must_use]
+    pub fn with_query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Only matches requests with exactly the provided body.
+    #[must_use]
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = Some(BodyMatcher::Exact(body.into()));
+        self
+    }
+
+    /// Only matches requests whose body is JSON equal to the provided value, regardless of its
+    /// formatting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value fails to serialize.
+    #[must_use]
+    pub fn with_json<B: Serialize>(mut self, body: &B) -> Self {
+        let body = serde_json::to_value(body).expect("failed to serialize mock body");
+        self.body = Some(BodyMatcher::Json(body));
+        self
+    }
+
+    #[must_use]
+    pub fn respond_with(mut self, response: MockResponse) -> Self {
+        self.response = response;
+        self
+    }
+
+    fn matches(&self, request: &CapturedRequest) -> bool {
+        let mut route = self.route.split('/');
+        let mut path = request.path.split('/');
+        let route_matches = loop {
+            match (route.next(), path.next()) {
+                (None, None) => break true,
+                (Some(expected), Some(actual))
+                    if expected == actual
+                        || (expected.starts_with('{') && expected.ends_with('}')) => {}
+                _ => break false,
+            }
+        };
+
+        let query: Vec<(String, String)> =
+            serde_urlencoded::from_str(request.query.as_deref().unwrap_or("")).unwrap_or_default();
+        let body_matches = match &self.body {
+            None => true,
+            Some(BodyMatcher::Exact(body)) => *body == request.body,
+            Some(BodyMatcher::Json(body)) => {
+                request.json::<Value>().is_ok_and(|json| json == *body)
+            }
+        };
+
+        self.method == request.method
+            && route_matches
+            && self
+                .headers
+                .iter()
+                .all(|(name, value)| request.headers.get_all(name).iter().any(|v| v == value))
+            && self.query.iter().all(|pair| query.contains(pair))
+            && body_matches
+    }
+}
+
+#[derive(Default)]
+struct State {
+    mocks: Vec<Mock>,
+    requests: Vec<CapturedRequest>,
+}
+
+/// A local HTTP server answering requests with canned responses, for testing code built on
+/// [`ApiClient`](crate::ApiClient) without network access.
+///
+/// Mocks are tried in the order they were added, and requests matching none of them are
+/// answered with `404 Not Found`. All requests are captured for later assertions. The server
+/// stops when dropped.
+///
+/// # Examples
+/// ```
+/// # use bc_api_client::ApiClient;
+/// # use bc_api_client::mock::{Mock, MockResponse, MockServer};
+/// # use bc_api_client::request::Request;
+/// # use reqwest::Method;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mock = MockServer::start().await;
+/// mock.mock(
+///     Mock::new(Method::GET, "/users/{id}")
+///         .respond_with(MockResponse::new(200).with_json(&serde_json::json!({"id": 1}))),
+/// );
+///
+/// let client = ApiClient::<()>::new(reqwest::Client::new(), &mock.url(), ());
+/// let user: serde_json::Value = client.get("/users/1").request_json().await.unwrap().body;
+/// assert_eq!(user["id"], 1);
+/// assert_eq!(mock.requests()[0].path, "/users/1");
+/// # }
+/// ```
+pub struct MockServer {
+    url: String,
+    state: Arc<Mutex<State>>,
+    server: AbortHandle,
+}
+
+impl MockServer {
+    /// Starts a server on a random local port.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no local port can be bound.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind mock server");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("unbound listener")
+        );
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let shared = Arc::clone(&state);
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, Arc::clone(&shared)));
+            }
+        });
+        Self {
+            url,
+            state,
+            server: server.abort_handle(),
+        }
+    }
+
+    /// Returns the base url of the server, without a trailing slash.
+    #[must_use]
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Adds a mock answering the requests it matches.
+    pub fn mock(&self, mock: Mock) {
+        self.state().mocks.push(mock);
+    }
+
+    /// Returns all requests received so far, in order.
+    #[must_use]
+    pub fn requests(&self) -> Vec<CapturedRequest> {
+        self.state().requests.clone()
+    }
+
+    /// Removes all mocks and captured requests.
+    pub fn reset(&self) {
+        *self.state() = State::default();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) -> std::io::Result<()> {
+    let Some(request) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        let response = state
+            .mocks
+            .iter()
+            .find(|mock| mock.matches(&request))
+            .map_or_else(
+                || MockResponse::new(404).with_body("no mock matched the request"),
+                |mock| mock.response.clone(),
+            );
+        state.requests.push(request);
+        response
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nconnection: close\r\ncontent-length: {}\r\n",
+        response.status.as_u16(),
+        response.status.canonical_reason().unwrap_or_default(),
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        let _ = write!(
+            head,
+            "{name}: {}\r\n",
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+/// Reads a single HTTP/1.1 request, or nothing if the connection closes early.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<CapturedRequest>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    };
+    let mut body = buf.split_off(head_end + 4);
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let Some(method) = request_line.next().and_then(|method| method.parse().ok()) else {
+        return Ok(None);
+    };
+    let target = request_line.next().unwrap_or("/");
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':')
+            && let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.trim().as_bytes()),
+                HeaderValue::from_str(value.trim()),
+            )
+        {
+            headers.append(name, value);
+        }
+    }
+
+    let chunked = headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"chunked"));
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+        .unwrap_or_default();
+    let body = loop {
+        if chunked {
+            if let Some(body) = decode_chunked(&body) {
+                break body;
+            }
+        } else if body.len() >= length {
+            body.truncate(length);
+            break body;
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk[..read]);
+    };
+
+    Ok(Some(CapturedRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    }))
+}
+
+/// Decodes a chunked body, or returns `None` if it is incomplete.
+fn decode_chunked(mut raw: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = raw.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&raw[..line_end]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        raw = &raw[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(raw.get(..size)?);
+        raw = raw.get(size + 2..)?;
+    }
+}
+
+fn header_name(name: &str) -> HeaderName {
+    HeaderName::from_bytes(name.as_bytes()).expect("invalid header name")
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).expect("invalid header value")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClient;
+    use crate::auth::Bearer;
+    use crate::request::Request as _;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn matches_and_captures() {
+        let mock = MockServer::start().await;
+        mock.mock(
+            Mock::new(Method::POST, "/users/{id}/posts")
+                .with_header("authorization", "Bearer secret")
+                .with_query("draft", "true")
+                .with_json(&json!({"title": "hello", "tags": []}))
+                .respond_with(
+                    MockResponse::new(201)
+                        .with_header("x-request-id", "abc")
+                        .with_json(&json!({"id": 7})),
+                ),
+        );
+        let client =
+            ApiClient::<()>::new(reqwest::Client::new(), &mock.url(), Bearer::new("secret"));
+
+        let response = client
+            .clone()
+            .post("/users/1/posts")
+            .query(&[("draft", "true")])
+            .body(r#"{ "tags": [], "title": "hello" }"#)
+            .request_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(response.status, StatusCode::CREATED);
+        assert_eq!(response.body, json!({"id": 7}));
+        assert_eq!(response.headers.get("x-request-id").unwrap(), "abc");
+
+        // the query parameter does not match
+        let error = client
+            .clone()
+            .post("/users/1/posts")
+            .json(&json!({"title": "hello", "tags": []}))
+            .request()
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, Method::POST);
+        assert_eq!(requests[0].path, "/users/1/posts");
+        assert_eq!(requests[0].query.as_deref(), Some("draft=true"));
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret"));
+        assert_eq!(requests[1].json::<Value>().unwrap()["title"], "hello");
+
+        mock.reset();
+        assert!(mock.requests().is_empty());
+        let error = client.get("/users/1/posts").request().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn first_match_wins() {
+        let mock = MockServer::start().await;
+        mock.mock(
+            Mock::new(Method::GET, "/a")
+                .with_body("x")
+                .respond_with(MockResponse::new(500)),
+        );
+        mock.mock(Mock::new(Method::GET, "/a").respond_with(MockResponse::new(200).with_body("a")));
+        mock.mock(
+            Mock::new(Method::GET, "/{any}").respond_with(MockResponse::new(200).with_body("any")),
+        );
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &mock.url(), ());
+
+        let body = client.clone().get("/a").request_text().await.unwrap().body;
+        assert_eq!(body, "a");
+        let body = client.clone().get("/b").request_text().await.unwrap().body;
+        assert_eq!(body, "any");
+        let error = client.get("/a/b").request().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn chunked_body() {
+        assert_eq!(
+            decode_chunked(b"5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n").unwrap(),
+            b"hello world"
+        );
+        assert_eq!(decode_chunked(b"5\r\nhel"), None);
+        assert_eq!(decode_chunked(b"5\r\nhello\r\n"), None);
+    }
+}
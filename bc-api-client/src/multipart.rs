@@ -0,0 +1,296 @@
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, TryStreamExt};
+use mime_guess::Mime;
+use reqwest::Body;
+use reqwest::multipart::{self, Form};
+use tokio::io::AsyncReadExt;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of bytes read from a file at a time while uploading it.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// A `multipart/form-data` body, see [`ApiRequest::multipart`](crate::request::ApiRequest::multipart).
+///
+/// Only the sources of the parts are kept, so a fresh body can be built for every attempt of a
+/// retried request, with files being reopened and streamed from disk each time.
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct Multipart {
+    parts: Vec<(String, Part)>,
+}
+
+impl Multipart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a text field.
+    pub fn with_text(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.with_part(name, Part::text(value))
+    }
+
+    /// Adds an in-memory file, whose content type is guessed from its file name.
+    pub fn with_bytes(
+        self,
+        name: impl Into<String>,
+        file_name: impl Into<String>,
+        data: impl Into<Bytes>,
+    ) -> Self {
+        self.with_part(name, Part::bytes(data).with_file_name(file_name))
+    }
+
+    /// Adds a file streamed from disk, whose content type is guessed from its extension.
+    pub fn with_file(self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.with_part(name, Part::file(path))
+    }
+
+    /// Adds a customized part.
+    pub fn with_part(mut self, name: impl Into<String>, part: Part) -> Self {
+        self.parts.push((name.into(), part));
+        self
+    }
+
+    /// Builds the body to send.
+    ///
+    /// Files are only opened once the body is sent, so a missing file fails the request instead.
+    #[must_use]
+    pub fn form(&self) -> Form {
+        self.parts.iter().fold(Form::new(), |form, (name, part)| {
+            form.part(name.clone(), part.build())
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Text(String),
+    Bytes(Bytes),
+    /// A file along with its length when the part was created, if it could be read.
+    File(PathBuf, Option<u64>),
+}
+
+/// A single part of a [`Multipart`] body.
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct Part {
+    source: Source,
+    file_name: Option<String>,
+    mime: Option<Mime>,
+}
+
+impl Part {
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            file_name: None,
+            mime: None,
+        }
+    }
+
+    /// A text part without a content type.
+    pub fn text(value: impl Into<String>) -> Self {
+        Self::new(Source::Text(value.into()))
+    }
+
+    /// An in-memory part, sent as `application/octet-stream` unless it has a file name with a
+    /// known extension.
+    pub fn bytes(data: impl Into<Bytes>) -> Self {
+        Self::new(Source::Bytes(data.into()))
+    }
+
+    /// A part streamed from the file at `path`, named after it.
+    ///
+    /// The length of the file is read once here and sent along with every attempt, so the
+    /// request fails if the file changes in between.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let file_name = path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().into_owned());
+        // a missing file fails the request once it is sent
+        let len = std::fs::metadata(&path).ok().map(|metadata| metadata.len());
+        Self {
+            file_name,
+            ..Self::new(Source::File(path, len))
+        }
+    }
+
+    pub fn with_file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Overrides the guessed content type of the part.
+    pub fn with_mime(mut self, mime: Mime) -> Self {
+        self.mime = Some(mime);
+        self
+    }
+
+    fn build(&self) -> multipart::Part {
+        let part = match &self.source {
+            Source::Text(value) => multipart::Part::text(value.clone()),
+            Source::Bytes(data) => {
+                multipart::Part::stream_with_length(data.clone(), data.len() as u64)
+            }
+            Source::File(path, len) => {
+                let body = Body::wrap_stream(read_file(path.clone()));
+                match len {
+                    Some(len) => multipart::Part::stream_with_length(body, *len),
+                    None => multipart::Part::stream(body),
+                }
+            }
+        };
+        let mime = self.mime.clone().or_else(|| match &self.source {
+            Source::Text(_) => None,
+            Source::Bytes(_) | Source::File(..) => Some(
+                self.file_name
+                    .as_deref()
+                    .map_or(mime_guess::mime::APPLICATION_OCTET_STREAM, |file_name| {
+                        mime_guess::from_path(Path::new(file_name)).first_or_octet_stream()
+                    }),
+            ),
+        });
+        let part = match mime {
+            Some(mime) => part
+                .mime_str(mime.as_ref())
+                .expect("parsed content types are valid"),
+            None => part,
+        };
+        match &self.file_name {
+            Some(file_name) => part.file_name(file_name.clone()),
+            None => part,
+        }
+    }
+}
+
+/// Opens the file once the stream is first polled and reads it chunk by chunk.
+fn read_file(path: PathBuf) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync + 'static {
+    stream::once(tokio::fs::File::open(path))
+        .map_ok(|file| {
+            stream::try_unfold(file, |mut file| async move {
+                let mut chunk = vec![0; CHUNK_LEN];
+                let read = file.read(&mut chunk).await?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                chunk.truncate(read);
+                Ok(Some((Bytes::from(chunk), file)))
+            })
+        })
+        .try_flatten()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClient;
+    use crate::request::Request as _;
+    use crate::retry::RetryPolicy;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// Answers the first request with `503 Service Unavailable` and every other one with its own
+    /// head and body.
+    async fn serve_flaky_echo() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for connection in 0.. {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length: "))
+                            .map_or(0, |length| length.trim().parse().unwrap());
+                        if read == 0 || body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                let response = if connection == 0 {
+                    b"HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n"
+                        .to_vec()
+                } else {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n",
+                        request.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&request);
+                    response
+                };
+                stream.write_all(&response).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn uploads_and_retries() {
+        let url = serve_flaky_echo().await;
+        let path = std::env::temp_dir().join(format!("bc-api-client-{}.txt", std::process::id()));
+        std::fs::write(&path, "file contents").unwrap();
+
+        let retry = RetryPolicy::new()
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .with_non_idempotent(true);
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        let multipart = Multipart::new()
+            .with_text("title", "hello")
+            .with_bytes("image", "pixel.png", &b"\x89PNG"[..])
+            .with_file("notes", &path)
+            .with_part(
+                "raw",
+                Part::bytes(&b"{}"[..]).with_mime(mime_guess::mime::APPLICATION_JSON),
+            );
+        let echo = client
+            .post("/upload")
+            .retry(Some(retry))
+            .multipart(multipart)
+            .request_text()
+            .await
+            .unwrap()
+            .body;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(echo.contains("content-type: multipart/form-data; boundary="));
+        assert!(echo.contains("name=\"title\"\r\n\r\nhello\r\n"));
+        assert!(echo.contains(
+            "name=\"image\"; filename=\"pixel.png\"\r\nContent-Type: image/png\r\n\r\n\u{fffd}PNG\r\n"
+        ));
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        assert!(echo.contains(&format!(
+            "name=\"notes\"; filename=\"{file_name}\"\r\nContent-Type: text/plain\r\n\r\nfile contents\r\n"
+        )));
+        assert!(echo.contains("name=\"raw\"\r\nContent-Type: application/json\r\n\r\n{}\r\n"));
+    }
+
+    #[tokio::test]
+    async fn missing_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            while stream.read(&mut buf).await.is_ok_and(|read| read > 0) {}
+        });
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        let error = client
+            .post("/upload")
+            .multipart(Multipart::new().with_file("notes", "/nonexistent/notes.txt"))
+            .request()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, crate::ApiError::Request(_)));
+    }
+}
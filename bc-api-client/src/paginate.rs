@@ -0,0 +1,514 @@
+use crate::error::{DecodeError, ErrorBody};
+use crate::request::{ApiRequest, Request};
+use crate::{ApiClient, ApiError};
+use futures_util::stream::{self, Stream, TryStreamExt};
+use reqwest::{Method, Url};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use std::error::Error as StdError;
+
+/// Pages fetched at most by default, see [`Pagination::with_max_pages`].
+const DEFAULT_MAX_PAGES: usize = 1_000;
+
+#[derive(Clone, Debug)]
+enum Strategy {
+    Page {
+        page_param: String,
+        per_page_param: String,
+        per_page: u64,
+        first_page: u64,
+    },
+    Offset {
+        offset_param: String,
+        limit_param: String,
+        limit: u64,
+    },
+    Cursor {
+        cursor_param: String,
+        next_cursor: String,
+    },
+    Link,
+}
+
+/// Describes how a list endpoint is paged through, see [`ApiClient::paginate`].
+///
+/// The items of a page are taken from the response body, which is expected to be a JSON array
+/// unless [`Pagination::with_items`] points somewhere into the body.
+#[derive(Clone, Debug)]
+pub struct Pagination {
+    strategy: Strategy,
+    items: Option<String>,
+    max_pages: usize,
+}
+
+impl Pagination {
+    /// Requests numbered pages of `per_page` items, starting at page 1.
+    ///
+    /// Paging stops at the first page with less than `per_page` items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per_page` is zero.
+    #[must_use]
+    pub fn page(page_param: &str, per_page_param: &str, per_page: u64) -> Self {
+        assert!(per_page > 0, "pages must hold at least one item");
+        Self::new(Strategy::Page {
+            page_param: page_param.to_string(),
+            per_page_param: per_page_param.to_string(),
+            per_page,
+            first_page: 1,
+        })
+    }
+
+    /// Requests `limit` items at increasing offsets, starting at 0.
+    ///
+    /// Paging stops at the first page with less than `limit` items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    #[must_use]
+    pub fn offset(offset_param: &str, limit_param: &str, limit: u64) -> Self {
+        assert!(limit > 0, "pages must hold at least one item");
+        Self::new(Strategy::Offset {
+            offset_param: offset_param.to_string(),
+            limit_param: limit_param.to_string(),
+            limit,
+        })
+    }
+
+    /// Passes the cursor found at the JSON pointer `next_cursor` of a page body, e.g.
+    /// `/meta/next_cursor`, as the `cursor_param` query parameter of the next request.
+    ///
+    /// Paging stops once the cursor is missing, `null` or empty.
+    #[must_use]
+    pub fn cursor(cursor_param: &str, next_cursor: &str) -> Self {
+        Self::new(Strategy::Cursor {
+            cursor_param: cursor_param.to_string(),
+            next_cursor: next_cursor.to_string(),
+        })
+    }
+
+    /// Follows the `rel="next"` url of the RFC 5988 `Link` header until there is none.
+    ///
+    /// Paging also stops at links to another origin than the base url of the client, which
+    /// would otherwise receive the credentials of the client.
+    #[must_use]
+    pub fn link() -> Self {
+        Self::new(Strategy::Link)
+    }
+
+    fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            items: None,
+            max_pages: DEFAULT_MAX_PAGES,
+        }
+    }
+
+    /// Sets the page number of the first page for [`Pagination::page`].
+    #[must_use]
+    pub fn with_first_page(mut self, first: u64) -> Self {
+        if let Strategy::Page { first_page, .. } = &mut self.strategy {
+            *first_page = first;
+        }
+        self
+    }
+
+    /// Takes the items of a page from the array at the JSON pointer, e.g. `/data`.
+    #[must_use]
+    pub fn with_items(mut self, pointer: &str) -> Self {
+        self.items = Some(pointer.to_string());
+        self
+    }
+
+    /// Stops paging after the provided number of pages, even if there are more.
+    #[must_use]
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Returns the query parameters of the page with the index provided.
+    fn query(&self, index: u64) -> Vec<(String, String)> {
+        match &self.strategy {
+            Strategy::Page {
+                page_param,
+                per_page_param,
+                per_page,
+                first_page,
+            } => vec![
+                (page_param.clone(), (first_page + index).to_string()),
+                (per_page_param.clone(), per_page.to_string()),
+            ],
+            Strategy::Offset {
+                offset_param,
+                limit_param,
+                limit,
+            } => vec![
+                (offset_param.clone(), (index * limit).to_string()),
+                (limit_param.clone(), limit.to_string()),
+            ],
+            Strategy::Cursor { .. } | Strategy::Link => Vec::new(),
+        }
+    }
+
+    /// Returns the page size, if the strategy uses a fixed one.
+    fn page_size(&self) -> Option<u64> {
+        match &self.strategy {
+            Strategy::Page { per_page, .. } => Some(*per_page),
+            Strategy::Offset { limit, .. } => Some(*limit),
+            Strategy::Cursor { .. } | Strategy::Link => None,
+        }
+    }
+}
+
+/// Where the next page is fetched from.
+enum NextPage {
+    Query(Vec<(String, String)>),
+    Url(String),
+    Done,
+}
+
+impl<T, E: ErrorBody> ApiClient<T, E> {
+    /// Pages through a list endpoint, yielding the items of all pages one by one.
+    ///
+    /// Pages are only requested once the items of the previous page have been consumed. The
+    /// stream ends after an error.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use bc_api_client::ApiClient;
+    /// # use bc_api_client::paginate::Pagination;
+    /// # use futures_util::TryStreamExt;
+    /// # async fn example(client: ApiClient<()>) -> Result<(), bc_api_client::ApiError> {
+    /// #[derive(serde::Deserialize)]
+    /// struct User {
+    ///     id: u64,
+    /// }
+    ///
+    /// let pagination = Pagination::page("page", "per_page", 100).with_items("/data");
+    /// let users: Vec<User> = client.paginate("/users", pagination).try_collect().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn paginate<R: DeserializeOwned>(
+        &self,
+        route: &str,
+        pagination: Pagination,
+    ) -> impl Stream<Item = Result<R, ApiError<E>>> + use<T, E, R> {
+        let client = self.clone();
+        let route = route.to_string();
+        let state = (NextPage::Query(pagination.query(0)), 0);
+
+        stream::try_unfold(state, move |(next, index)| {
+            let client = client.clone();
+            let route = route.clone();
+            let pagination = pagination.clone();
+            async move {
+                let request = match next {
+                    _ if index >= pagination.max_pages => return Ok::<_, ApiError<E>>(None),
+                    NextPage::Query(query) => client.clone().get(&route).query(&query),
+                    NextPage::Url(url) => {
+                        let request = client.client.request(Method::GET, url);
+                        ApiRequest::new(request, &client, &route)
+                    }
+                    NextPage::Done => return Ok(None),
+                };
+                // links are relative to the url of the page that returned them
+                let page_url = request
+                    .try_clone()
+                    .and_then(|request| request.into_inner().build().ok())
+                    .map(|request| request.url().clone());
+                let response = request.request_json::<Value>().await?;
+                // a response may split its links across several headers
+                let link = response
+                    .header_values("link")
+                    .find_map(next_link)
+                    .zip(page_url)
+                    .and_then(|(link, page_url)| resolve(&client.base_url, &page_url, link));
+                let items = page_items::<R>(&pagination, response.status, response.body)?;
+
+                let index = index + 1;
+                let next = match &pagination.strategy {
+                    _ if pagination
+                        .page_size()
+                        .is_some_and(|size| (items.0.len() as u64) < size) =>
+                    {
+                        NextPage::Done
+                    }
+                    Strategy::Page { .. } | Strategy::Offset { .. } => {
+                        NextPage::Query(pagination.query(index as u64))
+                    }
+                    Strategy::Cursor { cursor_param, .. } => match items.1 {
+                        Some(cursor) => NextPage::Query(vec![(cursor_param.clone(), cursor)]),
+                        None => NextPage::Done,
+                    },
+                    Strategy::Link => link.map_or(NextPage::Done, NextPage::Url),
+                };
+                Ok(Some((
+                    stream::iter(items.0.into_iter().map(Ok)),
+                    (next, index),
+                )))
+            }
+        })
+        .try_flatten()
+    }
+}
+
+/// Decodes the items of a page along with the cursor of the next page, if any.
+fn page_items<R: DeserializeOwned>(
+    pagination: &Pagination,
+    status: reqwest::StatusCode,
+    mut body: Value,
+) -> Result<(Vec<R>, Option<String>), DecodeError> {
+    let decode_error = |path: &str, body: &Value, error: Box<dyn StdError + Send + Sync>| {
+        DecodeError::new(status, path.to_string(), body.to_string().as_bytes(), error)
+    };
+
+    let cursor = match &pagination.strategy {
+        Strategy::Cursor { next_cursor, .. } => match body.pointer(next_cursor) {
+            Some(Value::String(cursor)) if !cursor.is_empty() => Some(cursor.clone()),
+            Some(Value::Number(cursor)) => Some(cursor.to_string()),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    let pointer = pagination.items.as_deref().unwrap_or("");
+    let Some(items) = body.pointer_mut(pointer).map(Value::take) else {
+        let error = format!("no items found at '{pointer}'");
+        return Err(decode_error(pointer, &body, error.into()));
+    };
+    match serde_path_to_error::deserialize(&items) {
+        Ok(items) => Ok((items, cursor)),
+        Err(error) => {
+            let path = format!("{pointer}/{}", error.path());
+            Err(decode_error(&path, &items, error.into_inner().into()))
+        }
+    }
+}
+
+/// Returns the url of the `rel="next"` link of a `Link` header.
+fn next_link(header: &str) -> Option<&str> {
+    let mut rest = header;
+    while let Some(start) = rest.find('<') {
+        let end = start + rest[start..].find('>')?;
+        let params_end = rest[end..].find('<').map_or(rest.len(), |next| end + next);
+        let is_next = rest[end + 1..params_end].split(';').any(|param| {
+            param
+                .trim()
+                .trim_end_matches(',')
+                .strip_prefix("rel=")
+                .is_some_and(|rel| {
+                    rel.trim_matches('"')
+                        .split_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("next"))
+                })
+        });
+        if is_next {
+            return Some(&rest[start + 1..end]);
+        }
+        rest = &rest[params_end..];
+    }
+    None
+}
+
+/// Resolves a possibly relative link against the url of the page it was returned with, returning
+/// `None` if it points to another origin than the base url of the client.
+fn resolve(base_url: &str, page_url: &Url, link: &str) -> Option<String> {
+    let base = Url::parse(base_url).ok()?;
+    let url = page_url.join(link).ok()?;
+    if url.origin() != base.origin() {
+        tracing::warn!(%url, "not following a link to another origin");
+        return None;
+    }
+    Some(url.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with the headers and body returned by the handler for its target.
+    async fn serve(handler: fn(&str) -> (String, String)) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let read = stream.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..read]).to_string();
+                let target = head.split_whitespace().nth(1).unwrap_or_default();
+                let (headers, body) = handler(target);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nconnection: close\r\n{headers}content-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    fn param(target: &str, name: &str) -> Option<u64> {
+        let query = target.split_once('?')?.1;
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{name}=")))
+            .and_then(|value| value.parse().ok())
+    }
+
+    async fn collect(client: &ApiClient<()>, route: &str, pagination: Pagination) -> Vec<u64> {
+        client
+            .paginate::<u64>(route, pagination)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn page_and_offset() {
+        // five items, numbered from 1
+        let url = serve(|target| {
+            let (start, count) = match (param(target, "page"), param(target, "offset")) {
+                (Some(page), _) => ((page - 1) * 2, 2),
+                (_, Some(offset)) => (offset, param(target, "limit").unwrap()),
+                _ => unreachable!(),
+            };
+            let items: Vec<_> = (start + 1..=(start + count).min(5)).collect();
+            (String::new(), format!(r#"{{"data":{items:?}}}"#))
+        })
+        .await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+
+        let pagination = Pagination::page("page", "per_page", 2).with_items("/data");
+        assert_eq!(
+            collect(&client, "/items", pagination).await,
+            [1, 2, 3, 4, 5]
+        );
+        let pagination = Pagination::offset("offset", "limit", 5).with_items("/data");
+        assert_eq!(
+            collect(&client, "/items", pagination).await,
+            [1, 2, 3, 4, 5]
+        );
+
+        // pages are only fetched on demand
+        let pagination = Pagination::page("page", "per_page", 2).with_items("/data");
+        let mut items = Box::pin(client.paginate::<u64>("/items", pagination));
+        assert_eq!(items.next().await.unwrap().unwrap(), 1);
+
+        let error = client
+            .paginate::<u64>("/items", Pagination::offset("offset", "limit", 2))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(error.is_decode());
+    }
+
+    #[tokio::test]
+    async fn cursor() {
+        let url = serve(|target| {
+            let body = match target.split_once("cursor=").map(|(_, cursor)| cursor) {
+                None => r#"{"items":[1,2],"meta":{"next":"abc"}}"#,
+                Some("abc") => r#"{"items":[3],"meta":{"next":null}}"#,
+                Some(_) => unreachable!(),
+            };
+            (String::new(), body.to_string())
+        })
+        .await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        let pagination = Pagination::cursor("cursor", "/meta/next").with_items("/items");
+        assert_eq!(collect(&client, "/items", pagination).await, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn link_header_and_max_pages() {
+        // every page links to the next one
+        let url = serve(|target| {
+            let page = param(target, "page").unwrap_or(1);
+            let link = format!(
+                "link: </items?page={}>; rel=\"next\", </items?page=1>; rel=\"first\"\r\n",
+                page + 1
+            );
+            (link, format!("[{page}]"))
+        })
+        .await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        let pagination = Pagination::link().with_max_pages(3);
+        assert_eq!(collect(&client, "/items", pagination).await, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn relative_link_header() {
+        // query-only links point at the page they were returned with
+        let url = serve(|target| {
+            assert!(
+                target.starts_with("/v1/users"),
+                "unexpected target {target}"
+            );
+            let page = param(target, "page").unwrap_or(1);
+            let link = match page {
+                1 | 2 => format!("link: <?page={}>; rel=\"next\"\r\n", page + 1),
+                _ => String::new(),
+            };
+            (link, format!("[{page}]"))
+        })
+        .await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        assert_eq!(
+            collect(&client, "/v1/users", Pagination::link()).await,
+            [1, 2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn link_header_to_other_origin() {
+        let url = serve(|_| {
+            let link = "link: <https://example.com/items?page=2>; rel=\"next\"\r\n";
+            (link.to_string(), "[1]".to_string())
+        })
+        .await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        assert_eq!(collect(&client, "/items", Pagination::link()).await, [1]);
+    }
+
+    #[test]
+    fn link_header() {
+        let header = r#"<https://api.github.com/repositories/1/issues?page=2>; rel="next", <https://api.github.com/repositories/1/issues?page=5>; rel="last""#;
+        assert_eq!(
+            next_link(header),
+            Some("https://api.github.com/repositories/1/issues?page=2")
+        );
+        assert_eq!(
+            next_link(r#"</a?x=1,2>; rel="prev", </b>; rel="next""#),
+            Some("/b")
+        );
+        assert_eq!(next_link(r#"</a>; rel="prev""#), None);
+        let page = Url::parse("http://localhost/v1/users?page=1").unwrap();
+        let resolve = |base_url, link| resolve(base_url, &page, link);
+        assert_eq!(
+            resolve("http://localhost:80/v1", "/items").as_deref(),
+            Some("http://localhost/items")
+        );
+        assert_eq!(
+            resolve("http://localhost", "?page=2").as_deref(),
+            Some("http://localhost/v1/users?page=2")
+        );
+        assert_eq!(
+            resolve("http://localhost", "items?page=2").as_deref(),
+            Some("http://localhost/v1/items?page=2")
+        );
+        assert_eq!(
+            resolve("http://localhost", "http://localhost:8080/items"),
+            None
+        );
+        assert_eq!(resolve("http://localhost", "https://localhost/items"), None);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::response::Response;
+use reqwest::header::HeaderMap;
+use tokio::time::Instant;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header carrying the number of calls allowed per rate limit window.
+const LIMIT: &str = "x-ratelimit-limit";
+/// Header carrying the number of calls left in the current rate limit window.
+const REMAINING: &str = "x-ratelimit-remaining";
+/// Header carrying when the current rate limit window ends.
+const RESET: &str = "x-ratelimit-reset";
+/// `X-RateLimit-Reset` values above this are unix timestamps rather than seconds from now.
+const RESET_EPOCH_THRESHOLD: f64 = 1_000_000_000.0;
+
+type SharedBucket = Arc<Mutex<Bucket>>;
+
+/// The rate limit reported by a response, see
+/// [`Response::rate_limit`](crate::response::Response::rate_limit).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitStatus {
+    /// The number of calls allowed per window, from `X-RateLimit-Limit`.
+    pub limit: Option<u32>,
+    /// The number of calls left in the current window, from `X-RateLimit-Remaining`.
+    pub remaining: u32,
+    /// The time left until the window ends, from `X-RateLimit-Reset`.
+    pub reset: Option<Duration>,
+}
+
+impl RateLimitStatus {
+    /// Reads the rate limit headers, returning `None` unless the remaining calls are reported.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let remaining = header(headers, REMAINING)?.parse().ok()?;
+        Some(Self {
+            limit: header(headers, LIMIT).and_then(|value| value.parse().ok()),
+            remaining,
+            reset: header(headers, RESET)
+                .and_then(|value| value.parse().ok())
+                .and_then(parse_reset),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    /// Tokens added per second as configured.
+    base_rate: f64,
+    /// Tokens added per second currently, lower than the configured rate while paced.
+    rate: f64,
+    /// Until when the bucket is paced to the calls the server reported left in its window.
+    paced_until: Option<Instant>,
+    /// Tokens currently available, negative if calls are waiting for a token already.
+    tokens: f64,
+    /// When the tokens were last refilled, in the future while paused until the server resets
+    /// its rate limit window.
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(requests: u32, per: Duration) -> Self {
+        assert!(requests > 0, "rate limit must allow at least one request");
+        assert!(!per.is_zero(), "rate limit period must be positive");
+        let capacity = f64::from(requests);
+        let rate = capacity / per.as_secs_f64();
+        Self {
+            capacity,
+            base_rate: rate,
+            rate,
+            paced_until: None,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.updated {
+            return;
+        }
+        let mut tokens = self.tokens;
+        match self.paced_until {
+            Some(until) if now >= until => {
+                // paced until the window reset, at the configured rate afterwards
+                let paced = until.saturating_duration_since(self.updated).as_secs_f64();
+                let elapsed = (now - until.max(self.updated)).as_secs_f64();
+                tokens += paced * self.rate + elapsed * self.base_rate;
+                self.rate = self.base_rate;
+                self.paced_until = None;
+            }
+            _ => tokens += (now - self.updated).as_secs_f64() * self.rate,
+        }
+        self.tokens = tokens.min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Returns how long it takes to refill the provided number of tokens.
+    fn refill_time(&self, tokens: f64) -> Duration {
+        let seconds = match self.paced_until {
+            Some(until) => {
+                let paced = until.saturating_duration_since(self.updated).as_secs_f64();
+                let paced_tokens = paced * self.rate;
+                if tokens <= paced_tokens {
+                    tokens / self.rate
+                } else {
+                    paced + (tokens - paced_tokens) / self.base_rate
+                }
+            }
+            None => tokens / self.rate,
+        };
+        Duration::from_secs_f64(seconds)
+    }
+
+    /// Takes a token and returns how long the caller has to wait until it may use it.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= 1.0;
+        let paused = self.updated.saturating_duration_since(now);
+        if self.tokens < 0.0 {
+            paused + self.refill_time(-self.tokens)
+        } else {
+            paused
+        }
+    }
+
+    fn observe(&mut self, now: Instant, remaining: u32, reset: Option<Duration>) {
+        self.refill(now);
+        match reset {
+            Some(reset) if remaining == 0 => {
+                // the bucket resumes with a single token once the window resets, so the calls
+                // waiting in the meantime are spread out at the configured rate again
+                self.tokens = 1.0;
+                self.rate = self.base_rate;
+                self.paced_until = None;
+                self.updated = self.updated.max(now + reset);
+            }
+            Some(reset) => {
+                // spread the calls left over the rest of the window, unless the configured rate
+                // is slower anyway
+                self.updated = self.updated.min(now);
+                let rate = f64::from(remaining) / reset.as_secs_f64();
+                if rate < self.base_rate {
+                    self.rate = rate;
+                    self.paced_until = Some(now + reset);
+                    self.tokens = self.tokens.min(1.0);
+                } else {
+                    self.rate = self.base_rate;
+                    self.paced_until = None;
+                    self.tokens = self.tokens.min(f64::from(remaining));
+                }
+            }
+            None => {
+                if remaining > 0 {
+                    self.updated = self.updated.min(now);
+                }
+                self.tokens = self.tokens.min(f64::from(remaining));
+            }
+        }
+    }
+}
+
+/// A client-side token bucket rate limiter.
+///
+/// Calls wait until the bucket has a token to spend, so bursts up to the bucket capacity are
+/// dispatched right away and the rest are spread out at the configured rate. Clones share the
+/// same buckets, so a limiter attached to an [`ApiClient`](crate::ApiClient) applies to all its
+/// clones.
+///
+/// The limiter also adapts to the calls the server reports left via the `X-RateLimit-Remaining`
+/// header. Along with `X-RateLimit-Reset`, the calls left are spread evenly until the window
+/// resets whenever that is slower than the configured rate, which is restored afterwards. Once
+/// no calls are left, the limiter pauses until the window resets.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    global: SharedBucket,
+    routes: Arc<[(Arc<str>, SharedBucket)]>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `requests` calls per period.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests` or `per` is zero.
+    #[must_use]
+    pub fn new(requests: u32, per: Duration) -> Self {
+        Self {
+            global: Arc::new(Mutex::new(Bucket::new(requests, per))),
+            routes: Arc::new([]),
+        }
+    }
+
+    /// Adds a separate bucket for routes under `prefix`.
+    ///
+    /// Prefixes match whole path segments, so `/search` applies to `/search` and `/search/users`
+    /// but not to `/searchable`. Calls to these routes spend a token from both their route bucket
+    /// and the global one. If multiple prefixes match a route, the longest one is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests` or `per` is zero.
+    #[must_use]
+    pub fn with_route(self, prefix: &str, requests: u32, per: Duration) -> Self {
+        let bucket = (
+            Arc::from(prefix),
+            Arc::new(Mutex::new(Bucket::new(requests, per))),
+        );
+        Self {
+            global: self.global,
+            routes: self.routes.iter().cloned().chain([bucket]).collect(),
+        }
+    }
+
+    /// Returns the buckets that calls to the provided route spend tokens from.
+    pub(crate) fn route(&self, route: &str) -> RouteLimiter {
+        let bucket = self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| is_under(route, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, bucket)| Arc::clone(bucket));
+        RouteLimiter {
+            global: Arc::clone(&self.global),
+            route: bucket,
+        }
+    }
+}
+
+/// The buckets applying to a single route.
+#[derive(Clone, Debug)]
+pub(crate) struct RouteLimiter {
+    global: SharedBucket,
+    route: Option<SharedBucket>,
+}
+
+impl RouteLimiter {
+    /// Waits until a call may be dispatched.
+    pub(crate) async fn acquire(&self) {
+        let now = Instant::now();
+        let mut wait = lock(&self.global).reserve(now);
+        if let Some(route) = &self.route {
+            wait = wait.max(lock(route).reserve(now));
+        }
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Adapts to the rate limit reported by the server for the most specific bucket.
+    pub(crate) fn observe<T>(&self, response: &Response<T>) {
+        let Some(status) = response.rate_limit() else {
+            return;
+        };
+        let bucket = self.route.as_ref().unwrap_or(&self.global);
+        lock(bucket).observe(Instant::now(), status.remaining, status.reset);
+    }
+}
+
+fn lock(bucket: &Mutex<Bucket>) -> std::sync::MutexGuard<'_, Bucket> {
+    // a bucket is never left in an inconsistent state, so poisoning can be ignored
+    bucket
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Checks whether `route` is `prefix` itself or lies below it in the path hierarchy.
+fn is_under(route: &str, prefix: &str) -> bool {
+    route.strip_prefix(prefix).is_some_and(|rest| {
+        prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?', '#'])
+    })
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok().map(str::trim)
+}
+
+/// Converts an `X-RateLimit-Reset` value into the time left until the reset.
+///
+/// APIs disagree on whether the header holds seconds from now or a unix timestamp, large values
+/// are treated as the latter.
+fn parse_reset(value: f64) -> Option<Duration> {
+    let seconds = if value > RESET_EPOCH_THRESHOLD {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+        value - now.as_secs_f64()
+    } else {
+        value
+    };
+    Duration::try_from_secs_f64(seconds.max(0.0)).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::StatusCode;
+    use reqwest::header::HeaderName;
+
+    fn response(remaining: &str, reset: &str) -> Response<()> {
+        let headers = [(REMAINING, remaining), (RESET, reset)]
+            .into_iter()
+            .map(|(key, value)| (HeaderName::from_static(key), value.parse().unwrap()))
+            .collect();
+        Response::empty()
+            .with_status(StatusCode::OK)
+            .with_headers(headers)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(1)).route("/");
+        let start = Instant::now();
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_across_clones_and_routes() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(1)).with_route(
+            "/search",
+            1,
+            Duration::from_secs(1),
+        );
+        let start = Instant::now();
+        let search = limiter.clone().route("/search?q=foo");
+        search.acquire().await;
+        search.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        // other routes are only limited by the global bucket
+        let start = Instant::now();
+        limiter.route("/items").acquire().await;
+        limiter.route("/searchable").acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn adapts_to_server_limits() {
+        let limiter = RateLimiter::new(100, Duration::from_secs(1)).route("/");
+        let start = Instant::now();
+        limiter.observe(&response("1", "3"));
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+
+        // nothing left, pause until the window resets and spread the waiting calls afterwards
+        let start = Instant::now();
+        limiter.observe(&response("0", "3"));
+        let acquire = || async {
+            limiter.acquire().await;
+            start.elapsed()
+        };
+        let waited = tokio::join!(acquire(), acquire(), acquire());
+        assert_eq!(
+            waited,
+            (
+                Duration::from_secs(3),
+                Duration::from_millis(3010),
+                Duration::from_millis(3020)
+            )
+        );
+
+        // calls being left again ends the pause early
+        limiter.observe(&response("0", "60"));
+        limiter.observe(&response("99", "60"));
+        let start = Instant::now();
+        limiter.acquire().await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_calls_left_until_reset() {
+        let limiter = RateLimiter::new(100, Duration::from_secs(1)).route("/");
+        let start = Instant::now();
+        limiter.observe(&response("5", "60"));
+        let mut waited = Vec::new();
+        for _ in 0..7 {
+            limiter.acquire().await;
+            waited.push(start.elapsed().as_millis());
+        }
+        // the calls left are spread over the window, the configured rate applies afterwards
+        assert_eq!(waited, [0, 12_000, 24_000, 36_000, 48_000, 60_000, 60_010]);
+
+        // a window allowing more than the configured rate does not speed the bucket up
+        let limiter = RateLimiter::new(2, Duration::from_secs(1)).route("/");
+        let start = Instant::now();
+        limiter.observe(&response("100", "1"));
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn route_prefixes_match_segments() {
+        assert!(is_under("/search", "/search"));
+        assert!(is_under("/search/users", "/search"));
+        assert!(is_under("/search?q=foo", "/search"));
+        assert!(is_under("/v1/items", "/v1/"));
+        assert!(!is_under("/searchable", "/search"));
+        assert!(!is_under("/items", "/search"));
+    }
+
+    #[test]
+    fn reset_formats() {
+        assert_eq!(parse_reset(30.0), Some(Duration::from_secs(30)));
+        assert_eq!(
+            parse_reset(RESET_EPOCH_THRESHOLD + 1.0),
+            Some(Duration::ZERO)
+        );
+        let in_a_minute =
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_mins(1);
+        let reset = parse_reset(in_a_minute.as_secs_f64()).unwrap();
+        assert!(reset > Duration::from_secs(59) && reset <= Duration::from_mins(1));
+    }
+}
@@ -1,56 +1,130 @@
-use crate::ApiResult;
+use crate::ApiClient;
+use crate::cache::HttpCache;
+use crate::circuit::CircuitBreaker;
+use crate::error::{ErrorBody, Untyped};
+use crate::middleware::{Middleware, Next};
+use crate::multipart::Multipart;
+use crate::rate_limit::RouteLimiter;
 use crate::response::Response;
-use reqwest::RequestBuilder;
-use reqwest::StatusCode;
+use crate::retry::RetryPolicy;
+use crate::sse::{self, EventStream, Reconnect};
+use crate::stream::{self as body_stream, ByteStream, JsonStream};
+use crate::{ApiError, ApiResult};
+use futures_util::{TryStreamExt, stream};
+use reqwest::header::{ACCEPT, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Body, Client, RequestBuilder};
+use serde::Serialize;
 use serde::de::DeserializeOwned;
 
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
 #[allow(async_fn_in_trait)]
 pub trait Request {
+    /// The type error response bodies are decoded into.
+    type Error: ErrorBody;
+
     /// Dispatches an API call and attempts to decode the response body into a byte vector.
     ///
     /// # Errors
     ///
-    /// Throws an error if the request fails to complete or the body cannot be decoded.
-    async fn request(self) -> ApiResult<Vec<u8>>;
-    async fn request_empty(self) -> ApiResult<()>;
-    async fn request_text(self) -> ApiResult<String>;
-    async fn request_json<R: DeserializeOwned>(self) -> ApiResult<R>;
+    /// Throws an error if the request fails to complete, the server responds with an error
+    /// status or the body cannot be read.
+    async fn request(self) -> ApiResult<Vec<u8>, Self::Error>;
+    async fn request_empty(self) -> ApiResult<(), Self::Error>;
+    async fn request_text(self) -> ApiResult<String, Self::Error>;
+    async fn request_json<R: DeserializeOwned>(self) -> ApiResult<R, Self::Error>;
+
+    /// Dispatches an API call and attempts to decode the XML response body.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete, the server responds with an error
+    /// status or the body cannot be decoded.
+    #[cfg(feature = "xml")]
+    async fn request_xml<R: DeserializeOwned>(self) -> ApiResult<R, Self::Error>;
+
+    /// Dispatches an API call and attempts to decode the `MessagePack` response body.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete, the server responds with an error
+    /// status or the body cannot be decoded.
+    #[cfg(feature = "msgpack")]
+    async fn request_msgpack<R: DeserializeOwned>(self) -> ApiResult<R, Self::Error>;
+
+    /// Dispatches an API call and attempts to decode the CBOR response body.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete, the server responds with an error
+    /// status or the body cannot be decoded.
+    #[cfg(feature = "cbor")]
+    async fn request_cbor<R: DeserializeOwned>(self) -> ApiResult<R, Self::Error>;
+
+    /// Dispatches an API call and attempts to decode the `application/x-www-form-urlencoded`
+    /// response body.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete, the server responds with an error
+    /// status or the body cannot be decoded.
+    #[cfg(feature = "form")]
+    async fn request_form<R: DeserializeOwned>(self) -> ApiResult<R, Self::Error>;
+
+    /// Dispatches an API call and decodes the response body according to its content type, see
+    /// [`Response::try_into_decoded`](crate::response::Response::try_into_decoded).
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete, the server responds with an error
+    /// status or the body cannot be decoded.
+    async fn request_decoded<R: DeserializeOwned>(self) -> ApiResult<R, Self::Error>;
+
+    /// Dispatches an API call and returns the response as soon as its head arrives, with a body
+    /// that is received chunk by chunk, see [`Download`](crate::stream::Download).
+    ///
+    /// The bodies of error responses are read as a whole to build the error.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete or the server responds with an error
+    /// status.
+    async fn request_stream(self) -> ApiResult<ByteStream, Self::Error>;
+
+    /// Dispatches an API call and decodes every line of the streamed response body as a JSON
+    /// value.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete or the server responds with an error
+    /// status.
+    async fn request_ndjson<R: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> ApiResult<JsonStream<R>, Self::Error>;
+
+    /// Dispatches an API call and parses the streamed response body as Server-Sent Events.
+    ///
+    /// Whenever the connection ends, the request is sent again after the delay set by the server,
+    /// along with the `Last-Event-ID` header. The stream ends once the server answers with
+    /// `204 No Content` or an error. Requests with streaming bodies cannot be sent again, so
+    /// their stream ends along with the connection, with an error if it broke off.
+    ///
+    /// # Errors
+    ///
+    /// Throws an error if the request fails to complete or the server responds with an error
+    /// status.
+    async fn request_sse(self) -> ApiResult<EventStream<Self::Error>, Self::Error>
+    where
+        Self::Error: Send + 'static;
 }
 
 impl Request for RequestBuilder {
+    type Error = Untyped;
+
     async fn request(self) -> ApiResult<Vec<u8>> {
-        let response = self.send().await.map_err(|e| {
-            Response::empty()
-                .with_status(e.status().unwrap_or(StatusCode::BAD_REQUEST))
-                .with_body(e.to_string())
-        })?;
-        let status = response.status();
-        let headers = response
-            .headers()
-            .iter()
-            .map(|(key, value)| {
-                (
-                    key.to_string(),
-                    value.to_str().unwrap_or_default().to_string(),
-                )
-            })
-            .collect();
-        let bytes = response.bytes().await.map_err(|e| {
-            Response::empty()
-                .with_status(e.status().unwrap_or(StatusCode::BAD_REQUEST))
-                .with_body(e.to_string())
-        })?;
-
-        let response = Response::empty()
-            .with_status(status)
-            .with_headers(headers)
-            .with_body(bytes.to_vec());
-
-        if response.is_error() {
-            Err(response.into_text())
-        } else {
-            Ok(response)
-        }
+        read_response(self.send().await?).await
     }
 
     async fn request_empty(self) -> ApiResult<()> {
@@ -64,4 +138,542 @@ impl Request for RequestBuilder {
     async fn request_json<R: DeserializeOwned>(self) -> ApiResult<R> {
         self.request().await?.try_into_json()
     }
+
+    #[cfg(feature = "xml")]
+    async fn request_xml<R: DeserializeOwned>(self) -> ApiResult<R> {
+        self.request().await?.try_into_xml()
+    }
+
+    #[cfg(feature = "msgpack")]
+    async fn request_msgpack<R: DeserializeOwned>(self) -> ApiResult<R> {
+        self.request().await?.try_into_msgpack()
+    }
+
+    #[cfg(feature = "cbor")]
+    async fn request_cbor<R: DeserializeOwned>(self) -> ApiResult<R> {
+        self.request().await?.try_into_cbor()
+    }
+
+    #[cfg(feature = "form")]
+    async fn request_form<R: DeserializeOwned>(self) -> ApiResult<R> {
+        self.request().await?.try_into_form()
+    }
+
+    async fn request_decoded<R: DeserializeOwned>(self) -> ApiResult<R> {
+        self.request().await?.try_into_decoded()
+    }
+
+    async fn request_stream(self) -> ApiResult<ByteStream> {
+        stream_response(self.send().await?).await
+    }
+
+    async fn request_ndjson<R: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> ApiResult<JsonStream<R>> {
+        Ok(body_stream::ndjson(self.request_stream().await?))
+    }
+
+    async fn request_sse(self) -> ApiResult<EventStream> {
+        let request = self.header(ACCEPT, "text/event-stream");
+        let reconnect = request.try_clone().map(|request| -> Reconnect<Untyped> {
+            Box::new(move |last_id| {
+                let request = request.try_clone().map(|request| match last_id {
+                    Some(last_id) => request.header("last-event-id", last_id),
+                    None => request,
+                });
+                Box::pin(async move {
+                    // the request was cloned once, so it can be cloned again
+                    let request = request.expect("request is cloneable");
+                    request.request_stream().await
+                })
+            })
+        });
+        Ok(sse::events(request.request_stream().await?, reconnect))
+    }
+}
+
+/// Returns the status and headers of a response.
+pub(crate) fn response_head(response: &reqwest::Response) -> Response<()> {
+    Response::empty()
+        .with_status(response.status())
+        .with_headers(response.headers().clone())
+}
+
+/// Reads the body of a response, turning client and server error statuses into errors.
+pub(crate) async fn read_response(response: reqwest::Response) -> ApiResult<Vec<u8>> {
+    let head = response_head(&response);
+    let bytes = response.bytes().await?;
+    let response = head.with_body(bytes.to_vec());
+
+    if response.is_error() {
+        Err(ApiError::Status(Box::new(response)))
+    } else {
+        Ok(response)
+    }
+}
+
+/// Streams the body of a response, reading it as a whole for client and server error statuses.
+async fn stream_response(response: reqwest::Response) -> ApiResult<ByteStream> {
+    let head = response_head(&response);
+    if head.is_error() {
+        return Err(read_response(response)
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("error statuses always turn into errors")));
+    }
+    Ok(head.with_body(Box::pin(response.bytes_stream().map_err(ApiError::from))))
+}
+
+/// A request created by an [`ApiClient`](crate::ApiClient).
+///
+/// Error responses are decoded into the error body type `E` declared by the client.
+#[must_use]
+pub struct ApiRequest<E = Untyped> {
+    builder: RequestBuilder,
+    auth: Arc<dyn Middleware>,
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    retry: Option<RetryPolicy>,
+    rate_limit: Option<RouteLimiter>,
+    circuit_breaker: Option<CircuitBreaker>,
+    cache: Option<Arc<HttpCache>>,
+    multipart: Option<Multipart>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<E> ApiRequest<E> {
+    pub(crate) fn new<T>(builder: RequestBuilder, client: &ApiClient<T, E>, route: &str) -> Self {
+        Self {
+            builder,
+            auth: Arc::clone(&client.auth),
+            middleware: Arc::clone(&client.middleware),
+            retry: client.retry,
+            rate_limit: client
+                .rate_limit
+                .as_ref()
+                .map(|rate_limit| rate_limit.route(route)),
+            circuit_breaker: client.circuit_breaker.clone(),
+            cache: client.cache.clone(),
+            multipart: None,
+            _error: PhantomData,
+        }
+    }
+
+    /// Modifies the underlying [`RequestBuilder`].
+    pub fn map<F: FnOnce(RequestBuilder) -> RequestBuilder>(self, f: F) -> Self {
+        Self {
+            builder: f(self.builder),
+            ..self
+        }
+    }
+
+    /// Overrides the retry policy of the client for this request.
+    ///
+    /// Passing `None` dispatches the request exactly once.
+    pub fn retry(mut self, retry: Option<RetryPolicy>) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Returns the underlying [`RequestBuilder`].
+    pub fn into_inner(self) -> RequestBuilder {
+        self.builder
+    }
+
+    /// Returns a copy of the request, unless its body is a stream and cannot be copied.
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Self {
+            builder: self.builder.try_clone()?,
+            auth: Arc::clone(&self.auth),
+            middleware: Arc::clone(&self.middleware),
+            retry: self.retry,
+            rate_limit: self.rate_limit.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            cache: self.cache.clone(),
+            multipart: self.multipart.clone(),
+            _error: PhantomData,
+        })
+    }
+
+    pub fn header<K, V>(self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.map(|builder| builder.header(key, value))
+    }
+
+    pub fn headers(self, headers: HeaderMap) -> Self {
+        self.map(|builder| builder.headers(headers))
+    }
+
+    pub fn query<Q: Serialize + ?Sized>(self, query: &Q) -> Self {
+        self.map(|builder| builder.query(query))
+    }
+
+    pub fn json<B: Serialize + ?Sized>(self, body: &B) -> Self {
+        self.map(|builder| builder.json(body))
+    }
+
+    pub fn form<B: Serialize + ?Sized>(self, body: &B) -> Self {
+        self.map(|builder| builder.form(body))
+    }
+
+    pub fn body<B: Into<Body>>(self, body: B) -> Self {
+        self.map(|builder| builder.body(body))
+    }
+
+    /// Sends a `multipart/form-data` body.
+    ///
+    /// The body is built anew for every attempt, so unlike other streamed bodies it does not
+    /// prevent the request from being retried.
+    pub fn multipart(mut self, multipart: Multipart) -> Self {
+        self.multipart = Some(multipart);
+        self
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.map(|builder| builder.timeout(timeout))
+    }
+}
+
+impl<E: ErrorBody> Request for ApiRequest<E> {
+    type Error = E;
+
+    async fn request(self) -> ApiResult<Vec<u8>, E> {
+        let (client, request) = self.builder.build_split();
+        let mut request = request?;
+        if let Some(response) = self.cache.as_ref().and_then(|cache| cache.fresh(&request)) {
+            return Ok(response);
+        }
+        let mut attempt = 1;
+        loop {
+            let retry = retry_with(self.retry, &request, attempt);
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.acquire()?;
+            }
+            if let Some(rate_limit) = &self.rate_limit {
+                rate_limit.acquire().await;
+            }
+            let outgoing = with_multipart(self.multipart.as_ref(), &client, request)?;
+            let result = Next::new(&client, &self.middleware, self.auth.as_ref())
+                .run(outgoing)
+                .await;
+            observe(self.rate_limit.as_ref(), &result);
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.record(&result);
+            }
+
+            let Some((policy, next)) = retry else {
+                return result.map_err(ApiError::with_error_body);
+            };
+            let Some(delay) = policy.delay(attempt, &result) else {
+                return result.map_err(ApiError::with_error_body);
+            };
+            tokio::time::sleep(delay).await;
+            request = next;
+            attempt += 1;
+        }
+    }
+
+    async fn request_empty(self) -> ApiResult<(), E> {
+        Ok(self.request().await?.into_empty())
+    }
+
+    async fn request_text(self) -> ApiResult<String, E> {
+        Ok(self.request().await?.into_text())
+    }
+
+    async fn request_json<R: DeserializeOwned>(self) -> ApiResult<R, E> {
+        self.request()
+            .await?
+            .try_into_json()
+            .map_err(ApiError::with_error_body)
+    }
+
+    #[cfg(feature = "xml")]
+    async fn request_xml<R: DeserializeOwned>(self) -> ApiResult<R, E> {
+        self.request()
+            .await?
+            .try_into_xml()
+            .map_err(ApiError::with_error_body)
+    }
+
+    #[cfg(feature = "msgpack")]
+    async fn request_msgpack<R: DeserializeOwned>(self) -> ApiResult<R, E> {
+        self.request()
+            .await?
+            .try_into_msgpack()
+            .map_err(ApiError::with_error_body)
+    }
+
+    #[cfg(feature = "cbor")]
+    async fn request_cbor<R: DeserializeOwned>(self) -> ApiResult<R, E> {
+        self.request()
+            .await?
+            .try_into_cbor()
+            .map_err(ApiError::with_error_body)
+    }
+
+    #[cfg(feature = "form")]
+    async fn request_form<R: DeserializeOwned>(self) -> ApiResult<R, E> {
+        self.request()
+            .await?
+            .try_into_form()
+            .map_err(ApiError::with_error_body)
+    }
+
+    async fn request_decoded<R: DeserializeOwned>(self) -> ApiResult<R, E> {
+        self.request()
+            .await?
+            .try_into_decoded()
+            .map_err(ApiError::with_error_body)
+    }
+
+    async fn request_ndjson<R: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> ApiResult<JsonStream<R>, E> {
+        Ok(body_stream::ndjson(self.request_stream().await?))
+    }
+
+    async fn request_sse(self) -> ApiResult<EventStream<E>, E>
+    where
+        E: Send + 'static,
+    {
+        let request = self.header(ACCEPT, "text/event-stream");
+        let reconnect = request.try_clone().map(|request| -> Reconnect<E> {
+            Box::new(move |last_id| {
+                let request = request.try_clone().map(|request| match last_id {
+                    Some(last_id) => request.header("last-event-id", last_id),
+                    None => request,
+                });
+                Box::pin(async move {
+                    // the request was cloned once, so it can be cloned again
+                    let request = request.expect("request is cloneable");
+                    request.request_stream().await
+                })
+            })
+        });
+        Ok(sse::events(request.request_stream().await?, reconnect))
+    }
+
+    async fn request_stream(self) -> ApiResult<ByteStream, E> {
+        let (client, request) = self.builder.build_split();
+        let mut request = request?;
+        let mut attempt = 1;
+        loop {
+            let retry = retry_with(self.retry, &request, attempt);
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.acquire()?;
+            }
+            if let Some(rate_limit) = &self.rate_limit {
+                rate_limit.acquire().await;
+            }
+            let outgoing = with_multipart(self.multipart.as_ref(), &client, request)?;
+            let streamed = Mutex::new(None);
+            let result = Next::streaming(&client, &self.middleware, self.auth.as_ref(), &streamed)
+                .run(outgoing)
+                .await;
+            let result = match (
+                result,
+                streamed
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner),
+            ) {
+                // the head returned by the chain may have been changed by middleware
+                (Ok(response), Some(streamed)) => {
+                    let body: ByteStream =
+                        Box::pin(streamed.bytes_stream().map_err(ApiError::from));
+                    Ok(response.with_body(body))
+                }
+                // a middleware answered on its own
+                (Ok(mut response), None) => {
+                    let body = bytes::Bytes::from(std::mem::take(&mut response.body));
+                    let body: ByteStream = Box::pin(stream::once(async { Ok(body) }));
+                    Ok(response.with_body(body))
+                }
+                (Err(error), _) => Err(error),
+            };
+            observe(self.rate_limit.as_ref(), &result);
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.record(&result);
+            }
+
+            let Some((policy, next)) = retry else {
+                return result.map_err(ApiError::with_error_body);
+            };
+            let Some(delay) = policy.delay(attempt, &result) else {
+                return result.map_err(ApiError::with_error_body);
+            };
+            tokio::time::sleep(delay).await;
+            request = next;
+            attempt += 1;
+        }
+    }
+}
+
+/// Returns the retry policy along with a copy of the request to retry with, if the request may be
+/// retried after the provided attempt.
+fn retry_with(
+    retry: Option<RetryPolicy>,
+    request: &reqwest::Request,
+    attempt: u32,
+) -> Option<(RetryPolicy, reqwest::Request)> {
+    // requests with streaming bodies cannot be cloned and are therefore never retried
+    retry
+        .filter(|policy| policy.allows(request.method(), attempt))
+        .and_then(|policy| Some((policy, request.try_clone()?)))
+}
+
+/// Attaches a freshly built multipart body, if any, to an attempt of a request.
+fn with_multipart(
+    multipart: Option<&Multipart>,
+    client: &Client,
+    request: reqwest::Request,
+) -> reqwest::Result<reqwest::Request> {
+    match multipart {
+        Some(multipart) => RequestBuilder::from_parts(client.clone(), request)
+            .multipart(multipart.form())
+            .build(),
+        None => Ok(request),
+    }
+}
+
+/// Feeds the rate limit headers of a response into the rate limiter, if any.
+fn observe<T>(rate_limit: Option<&RouteLimiter>, result: &ApiResult<T>) {
+    if let Some(rate_limit) = rate_limit {
+        match result {
+            Ok(response) => rate_limit.observe(response),
+            Err(ApiError::Status(response)) => rate_limit.observe(response),
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::{Client, StatusCode};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Accepts a single connection and answers it with the raw bytes provided.
+    async fn serve_once(response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream.write_all(response).await.unwrap();
+            // keep the connection open until the client is done with it
+            let _ = stream.read(&mut buf).await;
+        });
+        addr.to_string()
+    }
+
+    #[tokio::test]
+    async fn error_status() {
+        let addr = serve_once(
+            b"HTTP/1.1 404 Not Found\r\ncontent-length: 9\r\nx-foo: bar\r\n\r\nnot found",
+        )
+        .await;
+        let error = Client::new()
+            .get(format!("http://{addr}"))
+            .request()
+            .await
+            .unwrap_err();
+        assert!(error.is_status());
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+        let response = error.response().unwrap();
+        assert_eq!(response.headers.get("x-foo").unwrap(), "bar");
+        assert_eq!(response.body, b"not found");
+    }
+
+    #[tokio::test]
+    async fn typed_error_body() {
+        #[derive(Debug, serde::Deserialize)]
+        struct MyError {
+            code: u16,
+            message: String,
+        }
+
+        let body = b"HTTP/1.1 422 Unprocessable Entity\r\ncontent-length: 36\r\n\r\n{\"code\": 7, \"message\": \"invalid id\"}";
+        let addr = serve_once(body).await;
+        let client = crate::ApiClient::<()>::new(Client::new(), &format!("http://{addr}"), ())
+            .with_error_body::<MyError>();
+        let error = client.get("/").request_json::<()>().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        let body = error.error_body().unwrap();
+        assert_eq!(body.code, 7);
+        assert_eq!(body.message, "invalid id");
+
+        // falls back to the raw body if it does not parse
+        let addr =
+            serve_once(b"HTTP/1.1 502 Bad Gateway\r\ncontent-length: 7\r\n\r\noh dear").await;
+        let client = crate::ApiClient::<()>::new(Client::new(), &format!("http://{addr}"), ())
+            .with_error_body::<MyError>();
+        let error = client.get("/").request_text().await.unwrap_err();
+        assert!(error.error_body().is_none());
+        assert_eq!(error.response().unwrap().body_to_utf8(), "oh dear");
+    }
+
+    #[tokio::test]
+    async fn decode_error() {
+        let addr =
+            serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 13\r\n\r\n{\"foo\": true}").await;
+        let error = Client::new()
+            .get(format!("http://{addr}"))
+            .request_json::<std::collections::HashMap<String, u8>>()
+            .await
+            .unwrap_err();
+        let ApiError::Decode(error) = error else {
+            panic!("expected decode error")
+        };
+        assert_eq!(error.status, StatusCode::OK);
+        assert_eq!(error.path, "foo");
+        assert_eq!(error.snippet, "{\"foo\": true}");
+    }
+
+    #[tokio::test]
+    async fn transport_errors() {
+        // nothing listens on the port of a dropped listener
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let error = Client::new()
+            .get(format!("http://{addr}"))
+            .request()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ApiError::Connect(_)));
+
+        // TLS errors are only told apart from other connection errors by a TLS backend
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        {
+            let addr = serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await;
+            let error = Client::new()
+                .get(format!("https://{addr}"))
+                .request()
+                .await
+                .unwrap_err();
+            assert!(matches!(error, ApiError::Tls(_)));
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+        let error = Client::new()
+            .get(format!("http://{addr}"))
+            .timeout(Duration::from_millis(50))
+            .request()
+            .await
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
 }
@@ -1,13 +1,26 @@
 use crate::ApiResult;
+use crate::error::DecodeError;
+use crate::rate_limit::RateLimitStatus;
 use reqwest::StatusCode;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, HeaderMap, RETRY_AFTER};
 use serde::de::DeserializeOwned;
 
-use std::collections::HashMap;
+use std::time::Duration;
+
+/// The path of the offending value along with the error of a format specific decoder.
+#[cfg(any(
+    feature = "xml",
+    feature = "msgpack",
+    feature = "cbor",
+    feature = "form"
+))]
+type PathError = (String, Box<dyn std::error::Error + Send + Sync>);
 
 #[derive(Clone, Debug)]
 pub struct Response<R> {
     pub status: StatusCode,
-    pub headers: HashMap<String, String>,
+    /// The response headers, looked up case-insensitively and keeping repeated headers.
+    pub headers: HeaderMap,
     pub body: R,
 }
 
@@ -15,7 +28,7 @@ impl<R: Default> Default for Response<R> {
     fn default() -> Self {
         Self {
             status: StatusCode::OK,
-            headers: HashMap::new(),
+            headers: HeaderMap::new(),
             body: R::default(),
         }
     }
@@ -41,17 +54,146 @@ impl Response<Vec<u8>> {
     ///
     /// # Errors
     ///
-    /// Returns an error if the response status is error or if deserialization fails.
+    /// Returns a [`DecodeError`] pointing at the offending value if deserialization fails.
     pub fn try_into_json<R: DeserializeOwned>(self) -> ApiResult<R> {
-        match serde_json::from_slice(&self.body) {
+        let mut deserializer = serde_json::Deserializer::from_slice(&self.body);
+        let decoded = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|err| (err.path().to_string(), err.into_inner()))
+            .and_then(|body| {
+                deserializer
+                    .end()
+                    .map(|()| body)
+                    .map_err(|err| (".".to_string(), err))
+            });
+        match decoded {
+            Ok(body) => Ok(self.with_body(body)),
+            Err((path, err)) => Err(DecodeError::new(self.status, path, &self.body, err).into()),
+        }
+    }
+
+    /// Attempts to deserialize an XML body into the expected type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DecodeError`] pointing at the offending value if deserialization fails.
+    #[cfg(feature = "xml")]
+    pub fn try_into_xml<R: DeserializeOwned>(self) -> ApiResult<R> {
+        let decoded = std::str::from_utf8(&self.body)
+            .map_err(|err| (".".to_string(), err.into()))
+            .and_then(|body| {
+                let mut deserializer = quick_xml::de::Deserializer::from_str(body);
+                serde_path_to_error::deserialize(&mut deserializer).map_err(path_error)
+            });
+        self.decoded(decoded)
+    }
+
+    /// Attempts to deserialize a `MessagePack` body into the expected type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DecodeError`] pointing at the offending value if deserialization fails.
+    #[cfg(feature = "msgpack")]
+    pub fn try_into_msgpack<R: DeserializeOwned>(self) -> ApiResult<R> {
+        let mut deserializer = rmp_serde::Deserializer::new(self.body.as_slice());
+        let decoded = serde_path_to_error::deserialize(&mut deserializer).map_err(path_error);
+        self.decoded(decoded)
+    }
+
+    /// Attempts to deserialize a CBOR body into the expected type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DecodeError`] if deserialization fails. The decoder does not track the
+    /// offending value, so the path is always the whole body.
+    #[cfg(feature = "cbor")]
+    pub fn try_into_cbor<R: DeserializeOwned>(self) -> ApiResult<R> {
+        let decoded = ciborium::de::from_reader(self.body.as_slice())
+            .map_err(|err: ciborium::de::Error<_>| (".".to_string(), err.into()));
+        self.decoded(decoded)
+    }
+
+    /// Attempts to deserialize a `application/x-www-form-urlencoded` body into the expected type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DecodeError`] if deserialization fails. Form bodies are flat, so the path is
+    /// always the whole body.
+    #[cfg(feature = "form")]
+    pub fn try_into_form<R: DeserializeOwned>(self) -> ApiResult<R> {
+        let decoded =
+            serde_urlencoded::from_bytes(&self.body).map_err(|err| (".".to_string(), err.into()));
+        self.decoded(decoded)
+    }
+
+    /// Deserializes the body according to its `content-type` header.
+    ///
+    /// XML, `MessagePack`, CBOR and form bodies are decoded by the matching feature, while every
+    /// other body, including one without a content type, is decoded as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DecodeError`] if deserialization fails, or if the body is in a format whose
+    /// feature is not enabled.
+    pub fn try_into_decoded<R: DeserializeOwned>(self) -> ApiResult<R> {
+        let content_type = self
+            .content_type()
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        match format_feature(&content_type) {
+            #[cfg(feature = "xml")]
+            Some("xml") => self.try_into_xml(),
+            #[cfg(feature = "msgpack")]
+            Some("msgpack") => self.try_into_msgpack(),
+            #[cfg(feature = "cbor")]
+            Some("cbor") => self.try_into_cbor(),
+            #[cfg(feature = "form")]
+            Some("form") => self.try_into_form(),
+            Some(feature) => {
+                let error = format!("decoding {content_type} requires the `{feature}` feature");
+                Err(DecodeError::new(self.status, ".".to_string(), &self.body, error).into())
+            }
+            None => self.try_into_json(),
+        }
+    }
+
+    /// Turns the outcome of decoding the body into a response, or a [`DecodeError`] carrying the
+    /// path and message of the format specific error.
+    #[cfg(any(
+        feature = "xml",
+        feature = "msgpack",
+        feature = "cbor",
+        feature = "form"
+    ))]
+    fn decoded<R>(self, decoded: Result<R, PathError>) -> ApiResult<R> {
+        match decoded {
             Ok(body) => Ok(self.with_body(body)),
-            Err(err) => Err(self
-                .with_status(StatusCode::BAD_REQUEST)
-                .with_body(err.to_string())),
+            Err((path, err)) => Err(DecodeError::new(self.status, path, &self.body, err).into()),
         }
     }
 }
 
+/// Returns the feature decoding bodies of the content type, or `None` for JSON.
+fn format_feature(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "application/xml" | "text/xml" => Some("xml"),
+        mime if mime.ends_with("+xml") => Some("xml"),
+        "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+            Some("msgpack")
+        }
+        "application/cbor" => Some("cbor"),
+        "application/x-www-form-urlencoded" => Some("form"),
+        _ => None,
+    }
+}
+
+/// Splits a deserialization error into the path of the offending value and the error itself.
+#[cfg(any(feature = "xml", feature = "msgpack"))]
+fn path_error<E: std::error::Error + Send + Sync + 'static>(
+    err: serde_path_to_error::Error<E>,
+) -> PathError {
+    (err.path().to_string(), err.into_inner().into())
+}
+
 impl Response<()> {
     #[must_use]
     pub fn empty() -> Self {
@@ -67,7 +209,7 @@ impl<R> Response<R> {
     }
 
     #[must_use]
-    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
         self.headers = headers;
         self
     }
@@ -83,11 +225,59 @@ impl<R> Response<R> {
     pub fn is_error(&self) -> bool {
         self.status.is_client_error() || self.status.is_server_error()
     }
+
+    /// Returns the first value of a header, unless it is not valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    /// Returns every value of a repeated header such as `Set-Cookie`, skipping those that are not
+    /// valid UTF-8.
+    pub fn header_values<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> + use<'a, R> {
+        self.headers
+            .get_all(name)
+            .into_iter()
+            .filter_map(|value| value.to_str().ok())
+    }
+
+    /// Returns the media type of the `Content-Type` header, without its parameters.
+    pub fn content_type(&self) -> Option<&str> {
+        let value = self.headers.get(CONTENT_TYPE)?.to_str().ok()?;
+        value.split(';').next().map(str::trim)
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get(CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Returns the `ETag` header, including its quotes and weak validator prefix if any.
+    pub fn etag(&self) -> Option<&str> {
+        self.headers.get(ETAG)?.to_str().ok()
+    }
+
+    /// Returns how long the `Retry-After` header asks to wait, whether it holds a number of
+    /// seconds or an HTTP date.
+    pub fn retry_after(&self) -> Option<Duration> {
+        crate::retry::parse_retry_after(self.headers.get(RETRY_AFTER)?.to_str().ok()?)
+    }
+
+    /// Returns the rate limit reported by the `X-RateLimit-*` headers, if any.
+    pub fn rate_limit(&self) -> Option<RateLimitStatus> {
+        RateLimitStatus::from_headers(&self.headers)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::ApiError;
+    use reqwest::header::HeaderValue;
     use serde::Deserialize;
     use serde_json::json;
 
@@ -102,8 +292,8 @@ mod test {
     #[allow(clippy::unit_cmp)]
     fn process_empty() {
         let status = StatusCode::OK;
-        let mut headers = HashMap::new();
-        headers.insert("foo".to_string(), "bar".to_string());
+        let mut headers = HeaderMap::new();
+        headers.insert("foo", HeaderValue::from_static("bar"));
 
         let response = Response::empty().with_headers(headers);
         assert_eq!(response.status, status);
@@ -189,6 +379,215 @@ mod test {
                 .try_into_json::<TestData>()
                 .is_err()
         );
+
+        let input_json_bytes = json!([{ "foo": 1, "bar": "a" }, { "foo": 2, "bar": 3 }])
+            .to_string()
+            .as_bytes()
+            .to_vec();
+        let error = Response::empty()
+            .with_status(StatusCode::CREATED)
+            .with_body(input_json_bytes)
+            .try_into_json::<Vec<TestData>>()
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::CREATED));
+        let ApiError::Decode(error) = error else {
+            panic!("expected decode error")
+        };
+        assert_eq!(error.path, "[1].bar");
+        assert!(error.snippet.starts_with("[{\"bar\""));
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn process_xml() {
+        let input = b"<data><foo>12</foo><bar>mybar</bar></data>".to_vec();
+        let response = Response::empty()
+            .with_body(input)
+            .try_into_xml::<TestData>()
+            .unwrap();
+        assert_eq!(
+            response.body,
+            TestData {
+                foo: 12,
+                bar: "mybar".to_string(),
+                baz: None
+            }
+        );
+
+        let input = b"<data><foo>twelve</foo><bar>mybar</bar></data>".to_vec();
+        let ApiError::Decode(error) = Response::empty()
+            .with_body(input)
+            .try_into_xml::<TestData>()
+            .unwrap_err()
+        else {
+            panic!("expected decode error")
+        };
+        assert_eq!(error.path, "foo");
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn process_msgpack() {
+        let input =
+            rmp_serde::to_vec_named(&json!({ "foo": 12, "bar": "mybar", "baz": 3 })).unwrap();
+        let response = Response::empty()
+            .with_body(input)
+            .try_into_msgpack::<TestData>()
+            .unwrap();
+        assert_eq!(
+            response.body,
+            TestData {
+                foo: 12,
+                bar: "mybar".to_string(),
+                baz: Some(3)
+            }
+        );
+
+        let input = rmp_serde::to_vec_named(&json!({ "foo": 12, "bar": 3 })).unwrap();
+        let ApiError::Decode(error) = Response::empty()
+            .with_body(input)
+            .try_into_msgpack::<TestData>()
+            .unwrap_err()
+        else {
+            panic!("expected decode error")
+        };
+        assert_eq!(error.path, "bar");
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn process_cbor() {
+        let mut input = Vec::new();
+        ciborium::into_writer(&json!({ "foo": 12, "bar": "mybar" }), &mut input).unwrap();
+        let response = Response::empty()
+            .with_body(input)
+            .try_into_cbor::<TestData>()
+            .unwrap();
+        assert_eq!(response.body.foo, 12);
+        assert_eq!(response.body.bar, "mybar");
+
+        let error = Response::empty()
+            .with_body(vec![0xff])
+            .try_into_cbor::<TestData>()
+            .unwrap_err();
+        assert!(matches!(error, ApiError::Decode(_)));
+    }
+
+    #[cfg(feature = "form")]
+    #[test]
+    fn process_form() {
+        let response = Response::empty()
+            .with_body(b"foo=12&bar=my+bar&baz=3".to_vec())
+            .try_into_form::<TestData>()
+            .unwrap();
+        assert_eq!(
+            response.body,
+            TestData {
+                foo: 12,
+                bar: "my bar".to_string(),
+                baz: Some(3)
+            }
+        );
+
+        let error = Response::empty()
+            .with_body(b"foo=twelve&bar=mybar".to_vec())
+            .try_into_form::<TestData>()
+            .unwrap_err();
+        assert!(matches!(error, ApiError::Decode(_)));
+    }
+
+    #[test]
+    fn process_decoded() {
+        let decode = |content_type: &str, body: &[u8]| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+            Response::empty()
+                .with_headers(headers)
+                .with_body(body.to_vec())
+                .try_into_decoded::<TestData>()
+        };
+
+        let json = br#"{"foo": 12, "bar": "mybar"}"#;
+        assert_eq!(decode("application/json", json).unwrap().body.foo, 12);
+        assert_eq!(
+            decode("application/problem+json", json).unwrap().body.foo,
+            12
+        );
+        assert_eq!(decode("", json).unwrap().body.foo, 12);
+
+        let xml = b"<data><foo>12</foo><bar>mybar</bar></data>";
+        #[cfg(feature = "xml")]
+        {
+            assert_eq!(decode("text/xml", xml).unwrap().body.foo, 12);
+            assert_eq!(decode("application/atom+xml", xml).unwrap().body.foo, 12);
+        }
+        #[cfg(not(feature = "xml"))]
+        assert!(
+            decode("application/xml", xml)
+                .unwrap_err()
+                .to_string()
+                .contains("requires the `xml` feature")
+        );
+
+        let form = b"foo=12&bar=mybar";
+        #[cfg(feature = "form")]
+        assert_eq!(
+            decode("application/x-www-form-urlencoded; charset=utf-8", form)
+                .unwrap()
+                .body
+                .foo,
+            12
+        );
+        #[cfg(not(feature = "form"))]
+        assert!(
+            decode("application/x-www-form-urlencoded", form)
+                .unwrap_err()
+                .to_string()
+                .contains("requires the `form` feature")
+        );
+    }
+
+    #[test]
+    fn headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", HeaderValue::from_static("a=1"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2"));
+        headers.insert("x-binary", HeaderValue::from_bytes(b"\xff").unwrap());
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        headers.insert(ETAG, HeaderValue::from_static("W/\"abc\""));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("90"));
+        headers.insert("X-RateLimit-Limit", HeaderValue::from_static("60"));
+        headers.insert("X-RateLimit-Remaining", HeaderValue::from_static("0"));
+
+        let response = Response::empty().with_headers(headers);
+        assert_eq!(response.header("SET-COOKIE"), Some("a=1"));
+        assert_eq!(
+            response.header_values("set-cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(response.header("x-binary"), None);
+        assert!(response.headers.contains_key("x-binary"));
+        assert_eq!(response.header("missing"), None);
+        assert_eq!(response.content_type(), Some("text/html"));
+        assert_eq!(response.content_length(), Some(42));
+        assert_eq!(response.etag(), Some("W/\"abc\""));
+        assert_eq!(response.retry_after(), Some(Duration::from_secs(90)));
+        assert_eq!(
+            response.rate_limit(),
+            Some(RateLimitStatus {
+                limit: Some(60),
+                remaining: 0,
+                reset: None,
+            })
+        );
+
+        let response = Response::empty();
+        assert_eq!(response.content_type(), None);
+        assert_eq!(response.rate_limit(), None);
     }
 
     #[test]
@@ -0,0 +1,347 @@
+use crate::ApiResult;
+use crate::error::ApiError;
+use rand::Rng;
+use reqwest::{Method, StatusCode};
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Decides whether and when failed API calls are dispatched again.
+///
+/// Connection failures, `429 Too Many Requests` and server errors are retried with an
+/// exponential backoff, unless the server asks to wait for a specific time via `Retry-After`.
+/// Only idempotent methods are retried by default, since a failed `POST` might still have been
+/// processed by the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the total number of attempts, including the first one.
+    #[must_use]
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the delay before the first retry, which doubles with every further attempt until it
+    /// reaches `max`.
+    ///
+    /// `max` also caps the time the server may ask to wait via `Retry-After`, longer waits are
+    /// not retried at all.
+    #[must_use]
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Randomizes each backoff delay between half and the full delay, so that clients failing
+    /// at the same time do not retry in lockstep. Enabled by default.
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Retries non-idempotent methods like `POST` and `PATCH` as well.
+    #[must_use]
+    pub fn with_non_idempotent(mut self, non_idempotent: bool) -> Self {
+        self.non_idempotent = non_idempotent;
+        self
+    }
+
+    /// Returns whether a request with the provided method may be dispatched another time after
+    /// `attempt` attempts.
+    pub(crate) fn allows(&self, method: &Method, attempt: u32) -> bool {
+        attempt < self.max_attempts && (self.non_idempotent || is_idempotent(method))
+    }
+
+    /// Returns how long to wait before retrying the call that produced `result`, or `None` if it
+    /// must not be retried.
+    pub(crate) fn delay<T>(&self, attempt: u32, result: &ApiResult<T>) -> Option<Duration> {
+        let response = match result {
+            Err(ApiError::Connect(_)) => return Some(self.backoff(attempt)),
+            Err(ApiError::Status(response)) => response,
+            _ => return None,
+        };
+        let status = response.status;
+        if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+            return None;
+        }
+
+        match response.retry_after() {
+            Some(delay) if delay > self.max_backoff => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        if self.jitter {
+            backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            backoff
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Parses a `Retry-After` header, which holds either a number of seconds or an HTTP date.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = parse_http_date(value)?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Parses an IMF-fixdate such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(crate) fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let [_, day, month, year, time, "GMT"] = value.split_whitespace().collect::<Vec<_>>()[..]
+    else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut time = time.split(':').map(str::parse::<u64>);
+    let (Some(Ok(hours)), Some(Ok(minutes)), Some(Ok(seconds)), None) =
+        (time.next(), time.next(), time.next(), time.next())
+    else {
+        return None;
+    };
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    // days since the epoch of the proleptic gregorian calendar date, with years starting in
+    // march so that the leap day comes last
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+
+    let seconds = days * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClientBuilder;
+    use crate::request::Request;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers one connection after another with the raw responses provided, in order.
+    ///
+    /// Returns the address of the server and the number of requests it has received.
+    async fn serve_sequence(responses: Vec<&'static [u8]>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        (format!("http://{addr}"), hits)
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new().with_backoff(Duration::from_millis(1), Duration::from_millis(50))
+    }
+
+    const UNAVAILABLE: &[u8] =
+        b"HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 2\r\n\r\nok";
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = RetryPolicy::new()
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(false);
+        let delays: Vec<_> = (1..=6).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+
+        let policy = policy.with_jitter(true);
+        for attempt in 1..=6 {
+            let delay = policy.backoff(attempt);
+            assert!(delay >= delays[attempt as usize - 1] / 2);
+            assert!(delay <= delays[attempt as usize - 1]);
+        }
+    }
+
+    #[test]
+    fn retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_mins(2)));
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_hours(474_768))
+        );
+        // dates in the past mean that the call can be retried right away
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn idempotent_methods_only() {
+        let policy = RetryPolicy::new();
+        assert!(policy.allows(&Method::GET, 1));
+        assert!(policy.allows(&Method::PUT, 2));
+        assert!(!policy.allows(&Method::GET, 3));
+        assert!(!policy.allows(&Method::POST, 1));
+        assert!(policy.with_non_idempotent(true).allows(&Method::POST, 1));
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, hits) = serve_sequence(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let client = ApiClientBuilder::new(&url)
+            .with_retry(policy())
+            .build::<()>();
+        let response = client.get("/").request_text().await.unwrap();
+        assert_eq!(response.body, "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // gives up after the last attempt
+        let (url, hits) = serve_sequence(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let client = ApiClientBuilder::new(&url)
+            .with_retry(policy().with_max_attempts(2))
+            .build::<()>();
+        let error = client.get("/").request().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn honors_retry_after() {
+        let (url, hits) = serve_sequence(vec![
+            b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 0\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            OK,
+        ])
+        .await;
+        let client = ApiClientBuilder::new(&url)
+            .with_retry(policy())
+            .build::<()>();
+        client.get("/").request().await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // waiting longer than the maximum backoff is not worth it
+        let (url, hits) = serve_sequence(vec![
+            b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 60\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            OK,
+        ])
+        .await;
+        let client = ApiClientBuilder::new(&url)
+            .with_retry(policy())
+            .build::<()>();
+        let error = client.get("/").request().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn client_errors_and_post_are_not_retried() {
+        let (url, hits) = serve_sequence(vec![
+            b"HTTP/1.1 404 Not Found\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            OK,
+        ])
+        .await;
+        let client = ApiClientBuilder::new(&url)
+            .with_retry(policy())
+            .build::<()>();
+        client.get("/").request().await.unwrap_err();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        let (url, hits) = serve_sequence(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let client = ApiClientBuilder::new(&url)
+            .with_retry(policy())
+            .build::<()>();
+        client
+            .clone()
+            .post("/")
+            .body("foo")
+            .request()
+            .await
+            .unwrap_err();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // opting in for a single request
+        client
+            .clone()
+            .post("/")
+            .body("foo")
+            .retry(Some(policy().with_non_idempotent(true)))
+            .request()
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+}
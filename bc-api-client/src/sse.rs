@@ -0,0 +1,314 @@
+use crate::error::{ErrorBody, Untyped};
+use crate::middleware::BoxFuture;
+use crate::response::Response;
+use crate::stream::{ByteStream, LineBuffer};
+use crate::{ApiError, ApiResult};
+use futures_util::stream::{self, Stream, StreamExt};
+use reqwest::StatusCode;
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// How long to wait before reconnecting until the server sets a different delay.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+/// The events of a Server-Sent Events body, see
+/// [`Request::request_sse`](crate::request::Request::request_sse).
+pub type EventStream<E = Untyped> = Pin<Box<dyn Stream<Item = Result<Event, ApiError<E>>> + Send>>;
+
+/// Reopens an event stream, passing the id of the last event received.
+pub(crate) type Reconnect<E> =
+    Box<dyn Fn(Option<String>) -> BoxFuture<'static, ApiResult<ByteStream, E>> + Send + Sync>;
+
+/// A single Server-Sent Event.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    /// The id of the last event received so far, which need not be this one.
+    pub id: Option<String>,
+    /// The event type, `message` unless the server sets another one.
+    pub event: String,
+    /// The data lines of the event, joined by `\n`.
+    pub data: String,
+    /// The reconnection delay set by the server along with this event, if any.
+    pub retry: Option<Duration>,
+}
+
+/// Parses the lines of an event stream into events.
+#[derive(Debug, Default)]
+struct Parser {
+    last_id: Option<String>,
+    event: String,
+    data: String,
+    has_data: bool,
+    retry: Option<Duration>,
+    /// The reconnection delay last set by the server.
+    delay: Option<Duration>,
+}
+
+impl Parser {
+    /// Processes a line, returning the event it completes, if any.
+    fn line(&mut self, line: &[u8]) -> Option<Event> {
+        let line = String::from_utf8_lossy(line);
+        if line.is_empty() {
+            let event = std::mem::take(&mut self.event);
+            let data = std::mem::take(&mut self.data);
+            let retry = self.retry.take();
+            if !std::mem::take(&mut self.has_data) {
+                return None;
+            }
+            return Some(Event {
+                id: self.last_id.clone(),
+                event: if event.is_empty() {
+                    "message".to_string()
+                } else {
+                    event
+                },
+                data,
+                retry,
+            });
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((&line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            // an empty id resets the last event id
+            "id" if !value.contains('\0') => {
+                self.last_id = Some(value.to_string()).filter(|id| !id.is_empty());
+            }
+            "retry" => {
+                if let Ok(millis) = value.parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                    self.delay = self.retry;
+                }
+            }
+            // comments and unknown fields
+            _ => {}
+        }
+        None
+    }
+
+    /// Drops the partially received event when the connection is lost.
+    fn reset(&mut self) {
+        self.event.clear();
+        self.data.clear();
+        self.has_data = false;
+    }
+}
+
+struct State<E> {
+    body: Option<ByteStream>,
+    lines: LineBuffer,
+    parser: Parser,
+    pending: VecDeque<Event>,
+    reconnect: Option<Reconnect<E>>,
+}
+
+/// Parses a streamed body into events, reconnecting with the `Last-Event-ID` header whenever the
+/// connection ends, until the server answers a reconnect with `204 No Content` or an error.
+/// Without a way to reconnect, an error receiving the body ends the stream with that error.
+pub(crate) fn events<E: ErrorBody + Send + 'static>(
+    response: Response<ByteStream>,
+    reconnect: Option<Reconnect<E>>,
+) -> Response<EventStream<E>> {
+    let Response {
+        status,
+        headers,
+        body,
+    } = response;
+    let state = State {
+        body: Some(body),
+        lines: LineBuffer::default(),
+        parser: Parser::default(),
+        pending: VecDeque::new(),
+        reconnect,
+    };
+    let events = stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+
+            if let Some(body) = &mut state.body {
+                let next = body.next().await;
+                if let Some(Ok(chunk)) = next {
+                    let lines = state.lines.push(&chunk);
+                    let events = lines.iter().filter_map(|line| state.parser.line(line));
+                    state.pending.extend(events);
+                    continue;
+                }
+                // the connection is lost and reopened below
+                state.body = None;
+                state.lines = LineBuffer::default();
+                state.parser.reset();
+                if let Some(Err(error)) = next
+                    && state.reconnect.is_none()
+                {
+                    return Some((Err(error.with_error_body()), state));
+                }
+                continue;
+            }
+
+            let reconnect = state.reconnect.as_ref()?;
+            tokio::time::sleep(state.parser.delay.unwrap_or(DEFAULT_RETRY)).await;
+            match reconnect(state.parser.last_id.clone()).await {
+                Ok(response) if response.status == StatusCode::NO_CONTENT => return None,
+                Ok(response) => state.body = Some(response.body),
+                // unreachable servers are retried after the delay
+                Err(error) if error.is_connect() || error.is_timeout() => {}
+                Err(error) => {
+                    state.reconnect = None;
+                    return Some((Err(error), state));
+                }
+            }
+        }
+    });
+    Response {
+        status,
+        headers,
+        body: Box::pin(events),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClient;
+    use crate::error::DecodeError;
+    use crate::request::Request as _;
+    use bytes::Bytes;
+    use futures_util::TryStreamExt;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn parse(body: &str) -> Vec<Event> {
+        let mut lines = LineBuffer::default();
+        let mut parser = Parser::default();
+        let lines = lines.push(body.as_bytes());
+        lines.iter().filter_map(|line| parser.line(line)).collect()
+    }
+
+    #[test]
+    fn parses_events() {
+        let events = parse(
+            ": comment\n\
+             data: first\n\
+             data:second\n\
+             \n\
+             id: 7\n\
+             event: update\n\
+             retry: 250\n\
+             data\n\
+             \n\
+             event: ignored without data\n\
+             \n\
+             data: {\"a\": 1}\r\n\r\n\
+             id\n\
+             data: reset\n\n",
+        );
+        assert_eq!(
+            events,
+            [
+                Event {
+                    id: None,
+                    event: "message".to_string(),
+                    data: "first\nsecond".to_string(),
+                    retry: None,
+                },
+                Event {
+                    id: Some("7".to_string()),
+                    event: "update".to_string(),
+                    data: String::new(),
+                    retry: Some(Duration::from_millis(250)),
+                },
+                Event {
+                    id: Some("7".to_string()),
+                    event: "message".to_string(),
+                    data: "{\"a\": 1}".to_string(),
+                    retry: None,
+                },
+                Event {
+                    id: None,
+                    event: "message".to_string(),
+                    data: "reset".to_string(),
+                    retry: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn body_errors_end_the_stream() {
+        let error = serde_json::from_str::<u8>("x").unwrap_err();
+        let error = DecodeError::new(StatusCode::OK, String::new(), b"x", error);
+        let chunks = [Ok(Bytes::from("data: a\n\n")), Err(error.into())];
+        let response = Response::empty()
+            .with_status(StatusCode::OK)
+            .with_body(Box::pin(stream::iter(chunks)) as ByteStream);
+
+        let mut events = events::<Untyped>(response, None).body;
+        assert_eq!(events.next().await.unwrap().unwrap().data, "a");
+        assert!(events.next().await.unwrap().unwrap_err().is_decode());
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn reconnects_with_last_event_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&heads);
+        tokio::spawn(async move {
+            let bodies = [
+                "retry: 10\nid: 1\ndata: a\n\nid: 2\ndata: b\n\ndata: lost",
+                "id: 3\ndata: c\n\n",
+            ];
+            for connection in 0.. {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let read = stream.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                received.lock().unwrap().push(head);
+                let response = match bodies.get(connection) {
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: text/event-stream\r\n\
+                         content-length: {}\r\n\r\n{body}",
+                        body.len()
+                    ),
+                    None => "HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n".to_string(),
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        let events: Vec<Event> = client
+            .get("/events")
+            .request_sse()
+            .await
+            .unwrap()
+            .body
+            .try_collect()
+            .await
+            .unwrap();
+        let data: Vec<_> = events.iter().map(|event| event.data.as_str()).collect();
+        assert_eq!(data, ["a", "b", "c"]);
+
+        let heads = heads.lock().unwrap();
+        assert_eq!(heads.len(), 3);
+        assert!(heads[0].contains("accept: text/event-stream"));
+        assert!(!heads[0].contains("last-event-id"));
+        assert!(heads[1].contains("last-event-id: 2"));
+        assert!(heads[2].contains("last-event-id: 3"));
+    }
+}
@@ -0,0 +1,422 @@
+use crate::ApiError;
+use crate::error::DecodeError;
+use crate::response::Response;
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+/// The body of a streamed response, see
+/// [`Request::request_stream`](crate::request::Request::request_stream).
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, ApiError>> + Send>>;
+
+/// The values of a newline-delimited JSON body, see
+/// [`Request::request_ndjson`](crate::request::Request::request_ndjson).
+pub type JsonStream<R> = Pin<Box<dyn Stream<Item = Result<R, ApiError>> + Send>>;
+
+/// Splits a streamed body into lines.
+#[derive(Debug, Default)]
+pub(crate) struct LineBuffer {
+    buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Appends a chunk and returns the lines it completes, without their `\n` or `\r\n`.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        // the buffered bytes never contain a line break, so only the chunk has to be scanned
+        let mut start = 0;
+        let mut scanned = self.buffer.len();
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(offset) = self.buffer[scanned..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+        {
+            let end = scanned + offset;
+            let line = &self.buffer[start..end];
+            lines.push(line.strip_suffix(b"\r").unwrap_or(line).to_vec());
+            start = end + 1;
+            scanned = start;
+        }
+        self.buffer.drain(..start);
+        lines
+    }
+
+    /// Returns the last line if the body did not end with a line break.
+    pub(crate) fn finish(&mut self) -> Option<Vec<u8>> {
+        Some(std::mem::take(&mut self.buffer)).filter(|line| !line.is_empty())
+    }
+}
+
+/// Decodes every non-empty line of a streamed body as a JSON value.
+pub(crate) fn ndjson<R: DeserializeOwned + Send + 'static>(
+    response: Response<ByteStream>,
+) -> Response<JsonStream<R>> {
+    let Response {
+        status,
+        headers,
+        body,
+    } = response;
+    let state = (
+        body,
+        LineBuffer::default(),
+        VecDeque::<Vec<u8>>::new(),
+        false,
+    );
+    let values = stream::unfold(
+        state,
+        move |(mut body, mut lines, mut pending, mut done)| async move {
+            loop {
+                if let Some(line) = pending.pop_front() {
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    let value = decode_line(status, &line);
+                    return Some((value, (body, lines, pending, done)));
+                }
+                if done {
+                    return None;
+                }
+                match body.next().await {
+                    Some(Ok(chunk)) => pending.extend(lines.push(&chunk)),
+                    Some(Err(error)) => {
+                        done = true;
+                        return Some((Err(error), (body, lines, pending, done)));
+                    }
+                    None => {
+                        done = true;
+                        pending.extend(lines.finish());
+                    }
+                }
+            }
+        },
+    );
+    Response {
+        status,
+        headers,
+        body: Box::pin(values),
+    }
+}
+
+fn decode_line<R: DeserializeOwned>(
+    status: reqwest::StatusCode,
+    line: &[u8],
+) -> Result<R, ApiError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(line);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+        let path = error.path().to_string();
+        DecodeError::new(status, path, line, error.into_inner()).into()
+    })
+}
+
+/// How much of a [`Download`] has been written so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub written: u64,
+    /// The size of the whole body, if the server announced it.
+    pub total: Option<u64>,
+}
+
+/// A streamed response body that fails to download.
+#[derive(Debug)]
+pub enum DownloadError {
+    /// The body could not be received.
+    Api(ApiError),
+    /// The body could not be written.
+    Io(std::io::Error),
+    /// The SHA-256 digest of the body does not match the expected one.
+    Checksum { expected: Vec<u8>, actual: [u8; 32] },
+}
+
+impl From<ApiError> for DownloadError {
+    fn from(error: ApiError) -> Self {
+        Self::Api(error)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Api(error) => write!(f, "failed to receive body: {error}"),
+            Self::Io(error) => write!(f, "failed to write body: {error}"),
+            Self::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected sha256 {}, got {}",
+                hex::encode(expected),
+                hex::encode(actual)
+            ),
+        }
+    }
+}
+
+impl StdError for DownloadError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Api(error) => Some(error),
+            Self::Io(error) => Some(error),
+            Self::Checksum { .. } => None,
+        }
+    }
+}
+
+/// Writes a streamed response body to a file or any [`AsyncWrite`], chunk by chunk.
+///
+/// # Examples
+/// ```no_run
+/// # use bc_api_client::ApiClient;
+/// # use bc_api_client::request::Request;
+/// # use bc_api_client::stream::Download;
+/// # async fn example(client: ApiClient<()>) -> Result<(), Box<dyn std::error::Error>> {
+/// let response = client.get("/releases/latest.tar.gz").request_stream().await?;
+/// let expected = hex::decode("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")?;
+/// Download::new(response)
+///     .with_progress(|progress| println!("{} of {:?} bytes", progress.written, progress.total))
+///     .with_sha256(expected)
+///     .save("latest.tar.gz")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[must_use]
+pub struct Download {
+    response: Response<ByteStream>,
+    progress: Option<Box<dyn FnMut(Progress) + Send>>,
+    sha256: Option<Vec<u8>>,
+}
+
+impl Download {
+    pub fn new(response: Response<ByteStream>) -> Self {
+        Self {
+            response,
+            progress: None,
+            sha256: None,
+        }
+    }
+
+    /// Calls the provided function after every chunk written.
+    pub fn with_progress<F: FnMut(Progress) + Send + 'static>(mut self, progress: F) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Verifies the body against the provided SHA-256 digest once it is complete.
+    pub fn with_sha256<D: AsRef<[u8]>>(mut self, expected: D) -> Self {
+        self.sha256 = Some(expected.as_ref().to_vec());
+        self
+    }
+
+    /// Writes the body to the provided writer and returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the body cannot be received or written, or it does not match the expected
+    /// checksum. Whatever was received until then has been written already.
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        mut self,
+        writer: &mut W,
+    ) -> Result<u64, DownloadError> {
+        let total = self.response.content_length();
+        let mut hasher = self.sha256.as_ref().map(|_| bc_hash::Sha2_256::new());
+        let mut written = 0;
+
+        while let Some(chunk) = self.response.body.next().await {
+            let chunk = chunk?;
+            writer.write_all(&chunk).await?;
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&chunk);
+            }
+            written += chunk.len() as u64;
+            if let Some(progress) = &mut self.progress {
+                progress(Progress { written, total });
+            }
+        }
+        writer.flush().await?;
+
+        if let (Some(expected), Some(hasher)) = (self.sha256, hasher) {
+            let actual = hasher.finalize();
+            if expected != actual {
+                return Err(DownloadError::Checksum { expected, actual });
+            }
+        }
+        Ok(written)
+    }
+
+    /// Writes the body to a file at the provided path, replacing any existing file, and returns
+    /// the number of bytes written.
+    ///
+    /// The body is written to a temporary file next to the destination first, which is only
+    /// renamed to the destination once the body is complete and verified.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the body cannot be received or written, or it does not match the expected
+    /// checksum. The temporary file is removed again and any existing file left untouched in
+    /// that case.
+    pub async fn save<P: AsRef<Path>>(self, path: P) -> Result<u64, DownloadError> {
+        let path = path.as_ref();
+        let partial = partial_path(path);
+        let result = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            let written = self.write_to(&mut file).await?;
+            file.sync_all().await?;
+            drop(file);
+            tokio::fs::rename(&partial, path).await?;
+            Ok(written)
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        result
+    }
+}
+
+/// Returns a hidden, unique path in the directory of `path` to download it to.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{:016x}.part", rand::random::<u64>()));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ApiClient;
+    use crate::request::Request as _;
+    use futures_util::TryStreamExt;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    /// Answers every connection with a chunked body sent in the provided chunks.
+    async fn serve_chunks(status: &'static str, chunks: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                let head = format!(
+                    "HTTP/1.1 {status}\r\nconnection: close\r\ntransfer-encoding: chunked\r\n\r\n"
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                for chunk in chunks {
+                    let chunk = format!("{:x}\r\n{chunk}\r\n", chunk.len());
+                    stream.write_all(chunk.as_bytes()).await.unwrap();
+                    stream.flush().await.unwrap();
+                }
+                stream.write_all(b"0\r\n\r\n").await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn streams_body() {
+        let url = serve_chunks("200 OK", &["hello", " ", "world"]).await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+
+        let response = client.clone().get("/").request_stream().await.unwrap();
+        let body: Vec<Bytes> = response.body.try_collect().await.unwrap();
+        assert_eq!(body.concat(), b"hello world");
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&updates);
+        let response = client.clone().get("/").request_stream().await.unwrap();
+        let mut written = Vec::new();
+        let length = Download::new(response)
+            .with_progress(move |progress| log.lock().unwrap().push(progress.written))
+            .with_sha256(bc_hash::sha2_256("hello world"))
+            .write_to(&mut written)
+            .await
+            .unwrap();
+        assert_eq!(length, 11);
+        assert_eq!(written, b"hello world");
+        assert_eq!(updates.lock().unwrap().last(), Some(&11));
+
+        let dir = std::env::temp_dir().join(format!("bc-api-client-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("body");
+        let response = client.clone().get("/").request_stream().await.unwrap();
+        Download::new(response).save(&path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+
+        // a failed download leaves the existing file alone and cleans up after itself
+        let response = client.get("/").request_stream().await.unwrap();
+        let error = Download::new(response)
+            .with_sha256([0; 32])
+            .save(&path)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, DownloadError::Checksum { .. }));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ndjson_values() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Value {
+            a: u8,
+        }
+
+        let url = serve_chunks(
+            "200 OK",
+            &["{\"a\":1}\n{\"a\"", ":2}\r\n\n", "{\"a\":\"x\"}"],
+        )
+        .await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+
+        let mut values = client
+            .get("/")
+            .request_ndjson::<Value>()
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(values.next().await.unwrap().unwrap(), Value { a: 1 });
+        assert_eq!(values.next().await.unwrap().unwrap(), Value { a: 2 });
+        let error = values.next().await.unwrap().unwrap_err();
+        assert!(error.is_decode());
+        assert!(error.to_string().contains("at 'a'"));
+        assert!(values.next().await.is_none());
+    }
+
+    #[test]
+    fn line_buffer() {
+        let mut lines = LineBuffer::default();
+        assert!(lines.push(b"ab").is_empty());
+        assert_eq!(
+            lines.push(b"c\r\nd\n\ne"),
+            [b"abc".to_vec(), b"d".to_vec(), Vec::new()]
+        );
+        assert_eq!(lines.finish(), Some(b"e".to_vec()));
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[tokio::test]
+    async fn buffers_error_body() {
+        let url = serve_chunks("404 Not Found", &["not ", "found"]).await;
+        let client = ApiClient::<()>::new(reqwest::Client::new(), &url, ());
+        let Err(error) = client.get("/").request_stream().await else {
+            panic!("expected an error status");
+        };
+        assert_eq!(error.response().unwrap().body, b"not found");
+    }
+}
@@ -0,0 +1,671 @@
+use crate::middleware::{BoxFuture, Middleware, Next};
+use crate::response::Response;
+use crate::{ApiError, ApiResult};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Request, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Replaces redacted values in cassettes.
+const REDACTED: &str = "[REDACTED]";
+
+/// Whether a [`Vcr`] dispatches requests or replays them from its cassette.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VcrMode {
+    /// Dispatches every request and records it, overwriting the cassette.
+    Record,
+    /// Answers every request from the cassette without dispatching it.
+    Replay,
+    /// Replays if the cassette exists and records it otherwise.
+    #[default]
+    Auto,
+}
+
+/// The parts of a request compared to find its recorded interaction.
+///
+/// Method and path always have to match. The host is never compared, so cassettes stay valid
+/// when the base url of the client changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcrMatch {
+    pub query: bool,
+    pub body: bool,
+    pub headers: Vec<String>,
+}
+
+impl Default for VcrMatch {
+    fn default() -> Self {
+        Self {
+            query: true,
+            body: false,
+            headers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+struct Body {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    body: String,
+    /// Set if the body is not UTF-8 and stored as base64 instead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    base64: bool,
+}
+
+impl Body {
+    fn new(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(body) => Self {
+                body: body.to_string(),
+                base64: false,
+            },
+            Err(_) => Self {
+                body: BASE64.encode(bytes),
+                base64: true,
+            },
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        if self.base64 {
+            BASE64.decode(&self.body).unwrap_or_default()
+        } else {
+            self.body.as_bytes().to_vec()
+        }
+    }
+}
+
+/// A header value, stored as base64 if it is not UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Header {
+    Text(String),
+    Bytes { base64: String },
+}
+
+impl Header {
+    fn new(value: &HeaderValue) -> Self {
+        match value.to_str() {
+            Ok(value) => Self::Text(value.to_string()),
+            Err(_) => Self::Bytes {
+                base64: BASE64.encode(value.as_bytes()),
+            },
+        }
+    }
+
+    fn to_value(&self) -> Option<HeaderValue> {
+        match self {
+            Self::Text(value) => value.parse().ok(),
+            Self::Bytes { base64 } => HeaderValue::from_bytes(&BASE64.decode(base64).ok()?).ok(),
+        }
+    }
+}
+
+/// Recorded headers by lowercase name, keeping every value of repeated headers.
+type Headers = BTreeMap<String, Vec<Header>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct RecordedRequest {
+    method: String,
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
+    headers: Headers,
+    #[serde(flatten)]
+    body: Body,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Headers,
+    #[serde(flatten)]
+    body: Body,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug)]
+enum State {
+    Recording(Cassette),
+    /// The recorded interactions along with whether they have been replayed already.
+    Replaying(Vec<(Interaction, bool)>),
+}
+
+/// Records the API calls passing through it to a JSON cassette file and replays them later on,
+/// so that tests run deterministically and without network access. Every value of repeated
+/// headers is kept, and header values and bodies that are not UTF-8 are stored as base64.
+///
+/// Headers and values can be redacted before they are written to the cassette, the
+/// `Authorization` header always is. Note that the authentication method of the client runs
+/// after all middleware, so the headers it adds never reach the cassette in the first place.
+///
+/// When recording, the cassette is replaced by an empty one right away and written once the
+/// recorder is dropped along with the last clone of its client.
+///
+/// Replayed requests are matched against the recorded ones according to [`VcrMatch`], in
+/// recording order. Every recorded interaction is replayed once, unless it is the last match
+/// left for a request. The bodies of streamed calls are read as a whole while recording, and
+/// replayed in one piece.
+///
+/// # Panics
+///
+/// Calls panic if a replayed request matches no recorded interaction, or if the recorded
+/// cassette cannot be written.
+///
+/// # Examples
+/// ```no_run
+/// # use bc_api_client::ApiClientBuilder;
+/// # use bc_api_client::auth::Bearer;
+/// # use bc_api_client::vcr::{Vcr, VcrMode};
+/// let vcr = Vcr::new("tests/cassettes/users.json", VcrMode::Auto)
+///     .with_redacted_header("x-api-key")
+///     .with_redacted_value("my-secret-account-id");
+/// let client = ApiClientBuilder::new("https://api.example.com")
+///     .with_auth(Bearer::new("my-token"))
+///     .with_middleware(vcr)
+///     .build::<()>();
+/// ```
+#[derive(Debug)]
+pub struct Vcr {
+    path: PathBuf,
+    matching: VcrMatch,
+    redacted_headers: Vec<String>,
+    redacted_values: Vec<String>,
+    state: Mutex<State>,
+}
+
+impl Vcr {
+    /// Opens the cassette at the provided path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cassette is replayed but cannot be read or parsed, or if it is recorded but
+    /// cannot be written.
+    #[must_use]
+    pub fn new<P: AsRef<Path>>(path: P, mode: VcrMode) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let replay = match mode {
+            VcrMode::Record => false,
+            VcrMode::Replay => true,
+            VcrMode::Auto => path.exists(),
+        };
+        let state = if replay {
+            let cassette = std::fs::read(&path).unwrap_or_else(|error| {
+                panic!("failed to read cassette {}: {error}", path.display())
+            });
+            let cassette: Cassette = serde_json::from_slice(&cassette)
+                .unwrap_or_else(|error| panic!("invalid cassette {}: {error}", path.display()));
+            let interactions = cassette.interactions.into_iter();
+            State::Replaying(
+                interactions
+                    .map(|interaction| (interaction, false))
+                    .collect(),
+            )
+        } else {
+            // a recording without any calls still replaces an outdated cassette
+            let cassette = Cassette::default();
+            write_cassette(&path, &cassette);
+            State::Recording(cassette)
+        };
+        Self {
+            path,
+            matching: VcrMatch::default(),
+            redacted_headers: vec!["authorization".to_string()],
+            redacted_values: Vec::new(),
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Sets the parts of a request compared to find its recorded interaction.
+    #[must_use]
+    pub fn with_match(mut self, matching: VcrMatch) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    /// Replaces the value of the header in recorded requests and responses.
+    #[must_use]
+    pub fn with_redacted_header(mut self, header: &str) -> Self {
+        self.redacted_headers.push(header.to_ascii_lowercase());
+        self
+    }
+
+    /// Replaces all occurrences of the value in recorded urls, headers and bodies.
+    #[must_use]
+    pub fn with_redacted_value(mut self, value: &str) -> Self {
+        self.redacted_values.push(value.to_string());
+        self
+    }
+
+    fn redact(&self, value: &str) -> String {
+        self.redacted_values
+            .iter()
+            .filter(|redacted| !redacted.is_empty())
+            .fold(value.to_string(), |value, redacted| {
+                value.replace(redacted, REDACTED)
+            })
+    }
+
+    fn redact_body(&self, body: Body) -> Body {
+        if body.base64 {
+            body
+        } else {
+            Body {
+                body: self.redact(&body.body),
+                base64: false,
+            }
+        }
+    }
+
+    fn redact_headers(&self, headers: &HeaderMap) -> Headers {
+        let mut redacted = Headers::new();
+        for (name, value) in headers {
+            let name = name.as_str();
+            let value = if self
+                .redacted_headers
+                .iter()
+                .any(|redacted| redacted == name)
+            {
+                Header::Text(REDACTED.to_string())
+            } else {
+                match Header::new(value) {
+                    Header::Text(value) => Header::Text(self.redact(&value)),
+                    bytes @ Header::Bytes { .. } => bytes,
+                }
+            };
+            redacted.entry(name.to_string()).or_default().push(value);
+        }
+        redacted
+    }
+
+    fn record_request(&self, request: &Request) -> RecordedRequest {
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .unwrap_or_default();
+        RecordedRequest {
+            method: request.method().to_string(),
+            path: self.redact(request.url().path()),
+            query: request.url().query().map(|query| self.redact(query)),
+            headers: self.redact_headers(request.headers()),
+            body: self.redact_body(Body::new(body)),
+        }
+    }
+
+    fn matches(&self, recorded: &RecordedRequest, request: &RecordedRequest) -> bool {
+        recorded.method == request.method
+            && recorded.path == request.path
+            && (!self.matching.query || recorded.query == request.query)
+            && (!self.matching.body || recorded.body == request.body)
+            && self.matching.headers.iter().all(|header| {
+                let header = header.to_ascii_lowercase();
+                recorded.headers.get(&header) == request.headers.get(&header)
+            })
+    }
+
+    fn replay(&self, request: &RecordedRequest) -> Option<RecordedResponse> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let State::Replaying(interactions) = &mut *state else {
+            return None;
+        };
+        let mut matching = interactions
+            .iter_mut()
+            .filter(|(interaction, _)| self.matches(&interaction.request, request))
+            .peekable();
+        let first = matching
+            .peek()
+            .map(|(interaction, _)| interaction.response.clone());
+        match matching.find(|(_, replayed)| !replayed) {
+            Some((interaction, replayed)) => {
+                *replayed = true;
+                Some(interaction.response.clone())
+            }
+            None => first,
+        }
+    }
+
+    fn record(&self, request: RecordedRequest, result: &ApiResult<Vec<u8>>) {
+        let response = match result {
+            Ok(response) => response,
+            Err(ApiError::Status(response)) => &**response,
+            Err(_) => return,
+        };
+        let response = RecordedResponse {
+            status: response.status.as_u16(),
+            headers: self.redact_headers(&response.headers),
+            body: self.redact_body(Body::new(&response.body)),
+        };
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let State::Recording(cassette) = &mut *state {
+            cassette
+                .interactions
+                .push(Interaction { request, response });
+        }
+    }
+}
+
+impl Drop for Vcr {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        // a second panic while unwinding would abort the test run
+        if let State::Recording(cassette) = state
+            && !std::thread::panicking()
+        {
+            write_cassette(&self.path, cassette);
+        }
+    }
+}
+
+/// Writes a cassette to the provided path, creating its directory if needed.
+fn write_cassette(path: &Path, cassette: &Cassette) {
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let json = serde_json::to_vec_pretty(cassette).expect("cassettes always serialize");
+    if let Err(error) = std::fs::write(path, json) {
+        panic!("failed to write cassette {}: {error}", path.display());
+    }
+}
+
+impl Middleware for Vcr {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, ApiResult<Vec<u8>>> {
+        Box::pin(async move {
+            let recorded = self.record_request(&request);
+            let replaying = matches!(
+                *self.state.lock().unwrap_or_else(PoisonError::into_inner),
+                State::Replaying(_)
+            );
+            if !replaying {
+                let result = next.run(request).await;
+                let result = next.read_streamed(result).await;
+                self.record(recorded, &result);
+                return result;
+            }
+
+            let Some(response) = self.replay(&recorded) else {
+                panic!(
+                    "no interaction in cassette {} matches {} {}",
+                    self.path.display(),
+                    recorded.method,
+                    request.url()
+                );
+            };
+            let response = Response::empty()
+                .with_status(StatusCode::from_u16(response.status).unwrap_or_default())
+                .with_headers(replay_headers(&response.headers))
+                .with_body(response.body.to_bytes());
+            if response.is_error() {
+                Err(ApiError::Status(Box::new(response)))
+            } else {
+                Ok(response)
+            }
+        })
+    }
+}
+
+/// Rebuilds recorded headers, skipping names and values that are invalid.
+fn replay_headers(headers: &Headers) -> HeaderMap {
+    let mut replayed = HeaderMap::new();
+    for (name, values) in headers {
+        let Ok(name) = HeaderName::try_from(name.as_str()) else {
+            continue;
+        };
+        for value in values.iter().filter_map(Header::to_value) {
+            replayed.append(name.clone(), value);
+        }
+    }
+    replayed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::Bearer;
+    use crate::mock::{Mock, MockResponse, MockServer};
+    use crate::request::Request as _;
+    use crate::{ApiClient, ApiClientBuilder};
+    use futures_util::TryStreamExt;
+    use reqwest::Method;
+
+    fn cassette(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "bc-api-client-{name}-{}.json",
+            rand::random::<u64>()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn records_and_replays() {
+        let path = cassette("records-and-replays");
+        let mock = MockServer::start().await;
+        mock.mock(
+            Mock::new(Method::GET, "/users")
+                .with_query("page", "1")
+                .respond_with(MockResponse::new(200).with_body("page 1 of secret-id")),
+        );
+        mock.mock(
+            Mock::new(Method::GET, "/users").respond_with(
+                MockResponse::new(200)
+                    .with_header("x-token", "t")
+                    .with_body("page 2"),
+            ),
+        );
+        mock.mock(Mock::new(Method::GET, "/missing").respond_with(MockResponse::new(404)));
+
+        let record = |url: &str, mode| {
+            ApiClientBuilder::new(url)
+                .with_auth(Bearer::new("my-token"))
+                .with_middleware(
+                    Vcr::new(&path, mode)
+                        .with_redacted_header("x-token")
+                        .with_redacted_value("secret-id"),
+                )
+                .build::<()>()
+        };
+
+        let client = record(&mock.url(), VcrMode::Auto);
+        let first = client
+            .clone()
+            .get("/users?page=1")
+            .request_text()
+            .await
+            .unwrap();
+        let second = client
+            .clone()
+            .get("/users?page=2")
+            .request_text()
+            .await
+            .unwrap();
+        let missing = client.get("/missing").request().await.unwrap_err();
+        assert_eq!(first.body, "page 1 of secret-id");
+        assert_eq!(mock.requests().len(), 3);
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("secret-id"));
+        assert!(!json.contains("my-token"));
+        let cassette: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            cassette["interactions"][1]["response"]["headers"]["x-token"],
+            serde_json::json!([REDACTED])
+        );
+
+        // the mock server is gone, every call is answered from the cassette
+        drop(mock);
+        let client = record("http://127.0.0.1:9", VcrMode::Auto);
+        let replayed = client
+            .clone()
+            .get("/users?page=2")
+            .request_text()
+            .await
+            .unwrap();
+        assert_eq!(replayed.body, second.body);
+        assert_eq!(replayed.headers["x-token"], REDACTED);
+        let replayed = client
+            .clone()
+            .get("/users?page=1")
+            .request_text()
+            .await
+            .unwrap();
+        assert_eq!(replayed.body, "page 1 of [REDACTED]");
+        let replayed = client.get("/missing").request().await.unwrap_err();
+        assert_eq!(replayed.status(), missing.status());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn configurable_matching() {
+        let path = cassette("configurable-matching");
+        let mock = MockServer::start().await;
+        mock.mock(
+            Mock::new(Method::POST, "/echo")
+                .with_body("a")
+                .respond_with(MockResponse::new(200).with_body("got a")),
+        );
+        mock.mock(
+            Mock::new(Method::POST, "/echo")
+                .respond_with(MockResponse::new(200).with_body("got b")),
+        );
+
+        let matching = VcrMatch {
+            query: false,
+            body: true,
+            headers: vec!["x-version".to_string()],
+        };
+        let vcr = Vcr::new(&path, VcrMode::Record).with_match(matching.clone());
+        let client = ApiClientBuilder::new(&mock.url())
+            .with_middleware(vcr)
+            .build::<()>();
+        for body in ["a", "b"] {
+            let request = client.clone().post("/echo?ts=1").header("x-version", "1");
+            request.body(body).request().await.unwrap();
+        }
+        drop(client);
+
+        let vcr = Vcr::new(&path, VcrMode::Replay).with_match(matching);
+        let client = ApiClientBuilder::new(&mock.url())
+            .with_middleware(vcr)
+            .build::<()>();
+        let request = client.clone().post("/echo?ts=2").header("x-version", "1");
+        assert_eq!(
+            request.body("b").request_text().await.unwrap().body,
+            "got b"
+        );
+        let request = client.clone().post("/echo?ts=3").header("x-version", "1");
+        assert_eq!(
+            request.body("a").request_text().await.unwrap().body,
+            "got a"
+        );
+        // replaying the same interaction again
+        let request = client.post("/echo").header("x-version", "1");
+        assert_eq!(
+            request.body("a").request_text().await.unwrap().body,
+            "got a"
+        );
+        assert_eq!(mock.requests().len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_streamed_calls() {
+        let path = cassette("records-streamed-calls");
+        let mock = MockServer::start().await;
+        mock.mock(
+            Mock::new(Method::GET, "/export")
+                .respond_with(MockResponse::new(200).with_body("a\nb\n")),
+        );
+
+        let client = |url: &str, mode| {
+            ApiClientBuilder::new(url)
+                .with_middleware(Vcr::new(&path, mode))
+                .build::<()>()
+        };
+        let stream = |client: ApiClient<()>| async move {
+            let response = client.get("/export").request_stream().await.unwrap();
+            let chunks: Vec<_> = response.body.try_collect().await.unwrap();
+            chunks.concat()
+        };
+        assert_eq!(
+            stream(client(&mock.url(), VcrMode::Record)).await,
+            b"a\nb\n"
+        );
+
+        drop(mock);
+        let replayed = stream(client("http://127.0.0.1:9", VcrMode::Replay)).await;
+        assert_eq!(replayed, b"a\nb\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_raw_and_repeated_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("set-cookie", HeaderValue::from_static("a=1"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2, c=3"));
+        headers.append("x-raw", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+        headers.append("x-token", HeaderValue::from_static("secret"));
+
+        let path = cassette("raw-headers");
+        let vcr = Vcr::new(&path, VcrMode::Record).with_redacted_header("X-Token");
+        let recorded = vcr.redact_headers(&headers);
+        let json = serde_json::to_string(&recorded).unwrap();
+        assert!(json.contains(r#""x-raw":[{"base64":"Y2Fm6Q=="}]"#));
+
+        let replayed = replay_headers(&serde_json::from_str(&json).unwrap());
+        let values = |name| replayed.get_all(name).iter().cloned().collect::<Vec<_>>();
+        assert_eq!(values("set-cookie"), ["a=1", "b=2, c=3"]);
+        assert_eq!(
+            values("x-raw"),
+            [HeaderValue::from_bytes(b"caf\xe9").unwrap()]
+        );
+        assert_eq!(values("x-token"), [REDACTED]);
+
+        drop(vcr);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recording_replaces_cassette() {
+        let path = cassette("replaces-cassette");
+        std::fs::write(&path, r#"{"interactions":[{"outdated":true}]}"#).unwrap();
+        let vcr = Vcr::new(&path, VcrMode::Record);
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("outdated"));
+        drop(vcr);
+
+        let cassette: Cassette = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert!(cassette.interactions.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "matches GET")]
+    async fn unmatched_replay() {
+        let path = cassette("unmatched-replay");
+        std::fs::write(&path, r#"{"interactions":[]}"#).unwrap();
+        let vcr = Vcr::new(&path, VcrMode::Replay);
+        let _ = std::fs::remove_file(&path);
+        let client = ApiClientBuilder::new("http://127.0.0.1:9")
+            .with_middleware(vcr)
+            .build::<()>();
+        let _ = client.get("/").request().await;
+    }
+}
@@ -15,6 +15,7 @@ use syn::{parse_macro_input, Data, DeriveInput, Fields};
 pub fn derive_batch(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
+    let vis = input.vis;
     let struct_name = input.ident;
     let batch_name = format_ident!("{}Batch", struct_name);
 
@@ -41,11 +42,22 @@ pub fn derive_batch(input: TokenStream) -> TokenStream {
 
     let field_types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
 
+    let column_names: Vec<_> = field_names
+        .iter()
+        .map(|name| name.to_string().trim_start_matches("r#").to_string())
+        .collect();
+
     let expanded = quote! {
-        pub struct #batch_name {
+        #vis struct #batch_name {
             #(pub #field_names: Vec<#field_types>,)*
         }
 
+        impl ::bc_batch::Batch for #batch_name {
+            type Record = #struct_name;
+
+            const COLUMNS: &'static [&'static str] = &[#(#column_names,)*];
+        }
+
         impl From<Vec<#struct_name>> for #batch_name {
             fn from(items: Vec<#struct_name>) -> Self {
                 let mut batch = Self {
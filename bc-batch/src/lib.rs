@@ -1,5 +1,17 @@
 pub use bc_batch_derive::Batch;
 
+// lets the derive refer to `::bc_batch` from within this crate as well
+extern crate self as bc_batch;
+
+/// A column-oriented batch of records, implemented for `<Name>Batch` by `#[derive(Batch)]`.
+pub trait Batch: FromIterator<Self::Record> {
+    /// The record whose fields are batched.
+    type Record;
+
+    /// Names of the batched fields in declaration order, which are also their column names.
+    const COLUMNS: &'static [&'static str];
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -37,6 +49,7 @@ mod test {
         ];
 
         let batch = FooBatch::from(foos);
+        assert_eq!(FooBatch::COLUMNS, ["foo", "bar", "baz", "asd_jkl"]);
         assert_eq!(batch.foo, vec![Uuid::nil(); 3]);
         assert_eq!(batch.bar, ["first", "second", "third"]);
         assert_eq!(batch.baz, [None, Some(123), Some(0)]);
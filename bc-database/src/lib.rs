@@ -8,5 +8,7 @@ pub mod postgres;
 
 #[cfg(test)]
 mod test {
+    use bc_batch as _;
+    use serde as _;
     use tokio as _;
 }
@@ -0,0 +1,47 @@
+use sqlx::Error as SqlxError;
+use tracing::Instrument;
+use tracing::field::Empty;
+
+use std::future::Future;
+use std::time::Instant;
+
+/// Runs an insert inside a `db.insert` span carrying the table name and the number of rows.
+///
+/// Once the insert finishes, its duration is recorded in the `elapsed_ms` field of the span and
+/// failures are logged as warnings. Wrap record inserts in it, e.g. the `UNNEST` insert of a
+/// whole batch, to see their cost per request.
+///
+/// # Examples
+/// ```no_run
+/// # use bc_database::postgres::instrument_insert;
+/// # async fn insert(pool: &sqlx::PgPool, ids: Vec<i32>) -> Result<(), sqlx::Error> {
+/// let rows = ids.len();
+/// let insert = sqlx::query("INSERT INTO foo (id) SELECT * FROM UNNEST($1::INT4[])")
+///     .bind(ids)
+///     .execute(pool);
+/// instrument_insert("foo", rows, insert).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// Returns the error of the wrapped insert.
+pub async fn instrument_insert<F, T>(table: &str, rows: usize, insert: F) -> Result<T, SqlxError>
+where
+    F: Future<Output = Result<T, SqlxError>>,
+{
+    let span = tracing::info_span!("db.insert", table, rows, elapsed_ms = Empty);
+    let start = Instant::now();
+    let result = insert.instrument(span.clone()).await;
+    let elapsed_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+    span.record("elapsed_ms", elapsed_ms);
+
+    match &result {
+        Ok(_) => {
+            tracing::debug!(parent: &span, "inserted {rows} rows into {table} in {elapsed_ms}ms");
+        }
+        Err(error) => tracing::warn!(parent: &span, "insert into {table} failed: {error}"),
+    }
+    result
+}
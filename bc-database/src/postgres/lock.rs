@@ -0,0 +1,425 @@
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, Error as SqlxError, PgConnection, PgPool, Postgres, Row};
+
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+
+/// Postgres error code raised when `lock_timeout` expires.
+const LOCK_NOT_AVAILABLE: &str = "55P03";
+
+/// A Postgres advisory lock identified by a 64 bit key.
+///
+/// Session-scoped locks are bound to a dedicated pool connection that is held by the returned
+/// [`AdvisoryLockGuard`]. The guard takes the connection before the lock is requested, so if
+/// acquiring fails or is cancelled half-way, the connection is closed rather than returned to the
+/// pool possibly holding the lock. Transaction-scoped locks are released automatically when the
+/// transaction ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdvisoryLock {
+    key: i64,
+}
+
+impl AdvisoryLock {
+    /// Creates a lock whose key is derived from the provided name.
+    ///
+    /// The key is the 64 bit FNV-1a hash of the name, so every replica derives the same key for
+    /// the same name.
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let hash = name.bytes().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        });
+        Self::from_key(i64::from_ne_bytes(hash.to_ne_bytes()))
+    }
+
+    #[must_use]
+    pub fn from_key(key: i64) -> Self {
+        Self { key }
+    }
+
+    #[must_use]
+    pub fn key(&self) -> i64 {
+        self.key
+    }
+
+    /// Waits until the session-scoped lock is acquired.
+    ///
+    /// # Errors
+    ///
+    /// Errors if no connection can be acquired from the pool or the query fails.
+    pub async fn acquire(&self, pool: &PgPool) -> Result<AdvisoryLockGuard, SqlxError> {
+        let mut guard = AdvisoryLockGuard::new(self.key, pool.acquire().await?);
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(self.key)
+            .execute(guard.conn())
+            .await?;
+        Ok(guard)
+    }
+
+    /// Attempts to acquire the session-scoped lock without waiting.
+    ///
+    /// Returns `None` if the lock is held by another session.
+    ///
+    /// # Errors
+    ///
+    /// Errors if no connection can be acquired from the pool or the query fails.
+    pub async fn try_acquire(&self, pool: &PgPool) -> Result<Option<AdvisoryLockGuard>, SqlxError> {
+        let mut guard = AdvisoryLockGuard::new(self.key, pool.acquire().await?);
+        let acquired: bool = sqlx::query("SELECT pg_try_advisory_lock($1)")
+            .bind(self.key)
+            .fetch_one(guard.conn())
+            .await?
+            .try_get(0)?;
+        if !acquired {
+            guard.into_unlocked();
+            return Ok(None);
+        }
+        Ok(Some(guard))
+    }
+
+    /// Waits at most `timeout` for the session-scoped lock.
+    ///
+    /// Returns `None` if the lock could not be acquired in time.
+    ///
+    /// # Errors
+    ///
+    /// Errors if no connection can be acquired from the pool or the query fails for any other
+    /// reason than the timeout.
+    pub async fn acquire_timeout(
+        &self,
+        pool: &PgPool,
+        timeout: Duration,
+    ) -> Result<Option<AdvisoryLockGuard>, SqlxError> {
+        let mut guard = AdvisoryLockGuard::new(self.key, pool.acquire().await?);
+        // the timeout is scoped to a transaction so that it never outlives this call, while the
+        // session-scoped lock survives the commit
+        sqlx::query("BEGIN").execute(guard.conn()).await?;
+        sqlx::query("SELECT set_config('lock_timeout', $1, true)")
+            .bind(format!("{}ms", timeout.as_millis().max(1)))
+            .execute(guard.conn())
+            .await?;
+        let result = sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(self.key)
+            .execute(guard.conn())
+            .await;
+
+        match result {
+            Ok(_) => {
+                sqlx::query("COMMIT").execute(guard.conn()).await?;
+                Ok(Some(guard))
+            }
+            Err(SqlxError::Database(error))
+                if error.code().as_deref() == Some(LOCK_NOT_AVAILABLE) =>
+            {
+                sqlx::query("ROLLBACK").execute(guard.conn()).await?;
+                guard.into_unlocked();
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Waits until the lock is acquired for the duration of the current transaction.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the query fails.
+    pub async fn acquire_xact(&self, tx: &mut PgConnection) -> Result<(), SqlxError> {
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(self.key)
+            .execute(tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Attempts to acquire the lock for the duration of the current transaction without waiting.
+    ///
+    /// Returns `false` if the lock is held by another session.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the query fails.
+    pub async fn try_acquire_xact(&self, tx: &mut PgConnection) -> Result<bool, SqlxError> {
+        sqlx::query("SELECT pg_try_advisory_xact_lock($1)")
+            .bind(self.key)
+            .fetch_one(tx)
+            .await?
+            .try_get(0)
+    }
+}
+
+/// Holds a session-scoped advisory lock until released or dropped.
+///
+/// Since unlocking requires a round trip, dropping the guard closes the underlying connection
+/// instead, which makes Postgres release the lock. Prefer [`AdvisoryLockGuard::release`] to keep
+/// the connection in the pool.
+#[derive(Debug)]
+pub struct AdvisoryLockGuard {
+    key: i64,
+    conn: Option<PoolConnection<Postgres>>,
+}
+
+impl AdvisoryLockGuard {
+    fn new(key: i64, conn: PoolConnection<Postgres>) -> Self {
+        Self {
+            key,
+            conn: Some(conn),
+        }
+    }
+
+    #[must_use]
+    pub fn key(&self) -> i64 {
+        self.key
+    }
+
+    fn conn(&mut self) -> &mut PgConnection {
+        self.conn
+            .as_mut()
+            .expect("the connection is only taken on release")
+    }
+
+    /// Returns the connection to the pool, for a lock that turned out not to be acquired.
+    fn into_unlocked(mut self) {
+        drop(self.conn.take());
+    }
+
+    /// Checks that the session holding the lock is still alive.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection is broken, in which case Postgres has released the lock.
+    pub async fn ping(&mut self) -> Result<(), SqlxError> {
+        self.conn().ping().await
+    }
+
+    /// Releases the lock and returns the connection to the pool.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the unlock query fails, in which case the connection is closed.
+    pub async fn release(mut self) -> Result<(), SqlxError> {
+        if let Some(mut conn) = self.conn.take() {
+            let result = sqlx::query("SELECT pg_advisory_unlock($1)")
+                .bind(self.key)
+                .execute(&mut *conn)
+                .await;
+            if result.is_err() {
+                conn.close_on_drop();
+            }
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AdvisoryLockGuard {
+    fn drop(&mut self) {
+        if let Some(mut conn) = self.conn.take() {
+            conn.close_on_drop();
+        }
+    }
+}
+
+/// Leader election among replicas competing for the same advisory lock.
+///
+/// The replica that holds the lock is the leader, the others keep retrying until the leader
+/// releases the lock or its session dies.
+#[derive(Clone, Debug)]
+pub struct LeaderElection {
+    pool: PgPool,
+    lock: AdvisoryLock,
+    retry_interval: Duration,
+}
+
+impl LeaderElection {
+    #[must_use]
+    pub fn new(pool: PgPool, name: &str) -> Self {
+        Self {
+            pool,
+            lock: AdvisoryLock::new(name),
+            retry_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Sets how long a follower waits before trying to become the leader again.
+    #[must_use]
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Waits until this replica becomes the leader.
+    ///
+    /// Leadership is kept as long as the returned guard is alive.
+    ///
+    /// # Errors
+    ///
+    /// Errors if trying to acquire the lock fails.
+    pub async fn acquire(&self) -> Result<AdvisoryLockGuard, SqlxError> {
+        loop {
+            if let Some(guard) = self.lock.try_acquire(&self.pool).await? {
+                tracing::info!("acquired leadership (lock key {})", self.lock.key());
+                return Ok(guard);
+            }
+            tracing::debug!("not the leader, retrying in {:?}", self.retry_interval);
+            tokio::time::sleep(self.retry_interval).await;
+        }
+    }
+
+    /// Waits until this replica becomes the leader, runs the task and steps down afterwards.
+    ///
+    /// While the task runs, the connection holding the lock is pinged every retry interval. If
+    /// the session is gone, Postgres has already handed the lock to another replica, so the task
+    /// is cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Errors if acquiring or releasing the lock fails, or if leadership is lost while the task
+    /// runs.
+    pub async fn run<F, Fut, T>(&self, task: F) -> Result<T, SqlxError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut guard = self.acquire().await?;
+        let mut task = pin!(task());
+        let output = loop {
+            if let Ok(output) = tokio::time::timeout(self.retry_interval, &mut task).await {
+                break output;
+            }
+            if let Err(error) = guard.ping().await {
+                tracing::warn!("lost leadership (lock key {}): {error}", self.lock.key());
+                return Err(error);
+            }
+        };
+        guard.release().await?;
+        tracing::info!("released leadership (lock key {})", self.lock.key());
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::postgres::Config;
+
+    async fn pool(database: &str) -> PgPool {
+        Config::from_env()
+            .options
+            .with_database(database)
+            .connect()
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn key_from_name() {
+        assert_eq!(
+            AdvisoryLock::new("cron").key(),
+            AdvisoryLock::new("cron").key()
+        );
+        assert_ne!(
+            AdvisoryLock::new("cron").key(),
+            AdvisoryLock::new("cron2").key()
+        );
+        // FNV-1a of the empty string is the offset basis
+        assert_eq!(
+            AdvisoryLock::new("").key(),
+            i64::from_ne_bytes(0xcbf2_9ce4_8422_2325u64.to_ne_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn session_lock_is_exclusive() {
+        let pool = pool("advisory_lock_session").await;
+        let lock = AdvisoryLock::new("session_lock_is_exclusive");
+
+        let guard = lock.acquire(&pool).await.unwrap();
+        assert!(lock.try_acquire(&pool).await.unwrap().is_none());
+        assert!(
+            lock.acquire_timeout(&pool, Duration::from_millis(50))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        guard.release().await.unwrap();
+
+        let guard = lock.try_acquire(&pool).await.unwrap().unwrap();
+        drop(guard);
+        let guard = lock
+            .acquire_timeout(&pool, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(guard.is_some());
+    }
+
+    #[tokio::test]
+    async fn transaction_lock_is_released_on_commit() {
+        let pool = pool("advisory_lock_xact").await;
+        let lock = AdvisoryLock::new("transaction_lock_is_released_on_commit");
+
+        let mut tx_a = pool.begin().await.unwrap();
+        let mut tx_b = pool.begin().await.unwrap();
+        lock.acquire_xact(&mut tx_a).await.unwrap();
+        assert!(!lock.try_acquire_xact(&mut tx_b).await.unwrap());
+        tx_a.commit().await.unwrap();
+        assert!(lock.try_acquire_xact(&mut tx_b).await.unwrap());
+        tx_b.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn single_leader() {
+        let pool = pool("advisory_lock_leader").await;
+        let election = LeaderElection::new(pool.clone(), "single_leader")
+            .with_retry_interval(Duration::from_millis(10));
+
+        let leader = election.acquire().await.unwrap();
+        let follower = election.clone();
+        let handle = tokio::spawn(async move { follower.run(|| async { 42 }).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+
+        leader.release().await.unwrap();
+        assert_eq!(handle.await.unwrap().unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn leader_task_is_cancelled_when_session_dies() {
+        let pool = pool("advisory_lock_lost").await;
+        let election = LeaderElection::new(pool.clone(), "leader_task_is_cancelled")
+            .with_retry_interval(Duration::from_millis(10));
+
+        let leader = election.clone();
+        let handle = tokio::spawn(async move {
+            leader
+                .run(|| tokio::time::sleep(Duration::from_mins(1)))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // only the backend holding this election's lock, as 64 bit keys are split into the high
+        // and low halves in `classid` and `objid`
+        let terminated = sqlx::query(
+            "SELECT pg_terminate_backend(pid) FROM pg_locks \
+             WHERE locktype = 'advisory' AND objsubid = 1 \
+                 AND database = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+                 AND classid = (($1 >> 32) & 4294967295)::oid \
+                 AND objid = ($1 & 4294967295)::oid",
+        )
+        .bind(election.lock.key())
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(terminated.rows_affected(), 1);
+
+        let result = tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(result.is_err());
+        assert!(election.lock.try_acquire(&pool).await.unwrap().is_some());
+    }
+}
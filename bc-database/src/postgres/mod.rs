@@ -1,5 +1,15 @@
+mod instrument;
+mod lock;
 mod options;
+mod queue;
+mod stream;
+mod tenant;
+pub use instrument::instrument_insert;
+pub use lock::{AdvisoryLock, AdvisoryLockGuard, LeaderElection};
 pub use options::Options;
+pub use queue::{Job, JobQueue, NewJob};
+pub use stream::{stream_batches, stream_table};
+pub use tenant::Tenants;
 
 use sqlx::migrate::Migrator;
 use sqlx::{Error as SqlxError, PgPool};
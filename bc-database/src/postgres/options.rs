@@ -2,6 +2,7 @@ use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
 use sqlx::{ConnectOptions, Error as SqlxError, Executor, PgPool};
 use tracing::log::LevelFilter;
 
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Clone, Debug)]
@@ -22,7 +23,9 @@ impl Default for Options {
             .log_statements(LevelFilter::Trace);
         let pool = PgPoolOptions::new()
             .acquire_timeout(Duration::from_secs(5))
-            .idle_timeout(Duration::from_secs(2));
+            .idle_timeout(Duration::from_secs(2))
+            .acquire_time_level(LevelFilter::Debug)
+            .acquire_slow_level(LevelFilter::Warn);
 
         Self { connect, pool }
     }
@@ -69,6 +72,9 @@ impl Options {
         if let Ok(log_level) = dotenvy::var("DB_LOG_LEVEL") {
             opts.connect = opts.connect.log_statements(log_level.parse().unwrap());
         }
+        if let Ok(millis) = dotenvy::var("DB_SLOW_QUERY_MS") {
+            opts = opts.with_slow_statements(parse_threshold(&millis));
+        }
         if let Ok(seconds) = dotenvy::var("DB_ACQUIRE_TIMEOUT") {
             let duration = Duration::from_secs(seconds.parse().unwrap());
             opts.pool = opts.pool.acquire_timeout(duration);
@@ -89,6 +95,50 @@ impl Options {
         }
     }
 
+    /// Logs statements taking longer than `threshold` at warn level, instead of sqlx's default
+    /// of one second.
+    #[must_use]
+    pub fn with_slow_statements(self, threshold: Duration) -> Self {
+        Self {
+            connect: self
+                .connect
+                .log_slow_statements(LevelFilter::Warn, threshold),
+            pool: self.pool,
+        }
+    }
+
+    /// Sets the `search_path` of every pooled connection to the provided schema, followed by
+    /// `public` so that shared tables and extensions still resolve.
+    ///
+    /// The search path is set when a connection is opened and again whenever it is acquired from
+    /// the pool, so a connection never leaks into another schema even if a query changed it.
+    #[must_use]
+    pub fn with_search_path(self, schema: &str) -> Self {
+        let statement: Arc<str> = Arc::from(search_path(schema));
+        let on_acquire = Arc::clone(&statement);
+        let pool = self
+            .pool
+            .after_connect(move |conn, _| {
+                let statement = Arc::clone(&statement);
+                Box::pin(async move {
+                    conn.execute(statement.as_ref()).await?;
+                    Ok(())
+                })
+            })
+            .before_acquire(move |conn, _| {
+                let statement = Arc::clone(&on_acquire);
+                Box::pin(async move {
+                    conn.execute(statement.as_ref()).await?;
+                    Ok(true)
+                })
+            });
+
+        Self {
+            connect: self.connect,
+            pool,
+        }
+    }
+
     fn connect_lazy_with(self) -> PgPool {
         self.pool.connect_lazy_with(self.connect)
     }
@@ -134,3 +184,45 @@ impl Options {
         Ok(self.connect_with_db(&db))
     }
 }
+
+/// Builds the statement pointing unqualified names at `schema` first and at `public` second.
+pub(crate) fn search_path(schema: &str) -> String {
+    format!("SET search_path TO {}, public", quote_ident(schema))
+}
+
+/// Quotes a Postgres identifier, escaping embedded double quotes.
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Parses a threshold given in milliseconds, as in `DB_SLOW_QUERY_MS`.
+fn parse_threshold(millis: &str) -> Duration {
+    Duration::from_millis(
+        millis
+            .trim()
+            .parse()
+            .expect("invalid threshold in milliseconds"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slow_statement_threshold() {
+        assert_eq!(parse_threshold("250"), Duration::from_millis(250));
+        assert_eq!(parse_threshold(" 0\n"), Duration::ZERO);
+
+        let options = Options::default().with_slow_statements(parse_threshold("250"));
+        let connect = format!("{:?}", options.connect);
+        assert!(connect.contains("slow_statements_level: Warn"));
+        assert!(connect.contains("slow_statements_duration: 250ms"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid threshold in milliseconds")]
+    fn invalid_slow_statement_threshold() {
+        parse_threshold("1s");
+    }
+}
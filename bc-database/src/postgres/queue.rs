@@ -0,0 +1,514 @@
+use super::{Config, instrument_insert};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::{Error as SqlxError, Executor, PgPool, Row};
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Schema of the job table, applied idempotently by [`JobQueue::migrate`].
+const SCHEMA: &str = include_str!("../../migrations/20241012093000_job_queue.sql");
+
+/// A job that is about to be pushed onto a [`JobQueue`].
+#[derive(Clone, Debug)]
+pub struct NewJob<T> {
+    pub payload: T,
+    /// Jobs with higher priority are fetched first.
+    pub priority: i32,
+    /// The job is not fetched before this point in time. If `None`, it is runnable immediately.
+    pub run_at: Option<SystemTime>,
+    /// Number of times the job is attempted before being dead-lettered.
+    pub max_attempts: i32,
+}
+
+impl<T> NewJob<T> {
+    #[must_use]
+    pub fn new(payload: T) -> Self {
+        Self {
+            payload,
+            priority: 0,
+            run_at: None,
+            max_attempts: 3,
+        }
+    }
+
+    #[must_use]
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    #[must_use]
+    pub fn with_run_at(mut self, run_at: SystemTime) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+
+    #[must_use]
+    pub fn with_delay(self, delay: Duration) -> Self {
+        self.with_run_at(SystemTime::now() + delay)
+    }
+
+    #[must_use]
+    pub fn with_max_attempts(mut self, max_attempts: i32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+/// A job fetched from a [`JobQueue`].
+#[derive(Clone, Debug)]
+pub struct Job<T> {
+    pub id: i64,
+    pub payload: T,
+    /// Number of attempts so far, including the current one.
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// The error recorded by the last failed attempt.
+    pub last_error: Option<String>,
+}
+
+impl<T: DeserializeOwned> Job<T> {
+    fn from_row(row: &PgRow) -> Result<Self, SqlxError> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            payload: row.try_get::<Json<T>, _>("payload")?.0,
+            attempts: row.try_get("attempts")?,
+            max_attempts: row.try_get("max_attempts")?,
+            last_error: row.try_get("last_error")?,
+        })
+    }
+}
+
+/// A durable work queue backed by the `bc_jobs` Postgres table.
+///
+/// Workers fetch jobs with `FOR UPDATE SKIP LOCKED`, so any number of them can poll the same
+/// queue concurrently without handing out a job twice. A fetched job must either be completed or
+/// failed; failed jobs are retried with exponential backoff until they run out of attempts, after
+/// which they are dead-lettered.
+#[derive(Clone, Debug)]
+pub struct JobQueue {
+    pool: PgPool,
+    queue: Arc<str>,
+    poll_interval: Duration,
+    retry_delay: Duration,
+}
+
+impl JobQueue {
+    /// Creates a handle to the named queue.
+    ///
+    /// The `bc_jobs` table is expected to exist already, see [`JobQueue::migrate`] otherwise.
+    #[must_use]
+    pub fn new(pool: PgPool, queue: &str) -> Self {
+        Self {
+            pool,
+            queue: Arc::from(queue),
+            poll_interval: Duration::from_secs(1),
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+
+    /// Connects to Postgres, runs the configured migrations and makes sure the job table exists,
+    /// see [`JobQueue::migrate`].
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection or any of the migrations fail.
+    pub async fn connect(config: Config, queue: &str) -> Result<Self, SqlxError> {
+        let pool = config.connect_with_migration().await?;
+        Self::migrate(&pool).await?;
+        Ok(Self::new(pool, queue))
+    }
+
+    /// Creates the `bc_jobs` table and its index if they do not exist yet.
+    ///
+    /// The schema ships as the sqlx migration `migrations/20241012093000_job_queue.sql`, which
+    /// applications should copy into their own migrations to have it versioned and evolved along
+    /// with them. Since the schema only creates what is missing, running it here as well is
+    /// harmless. Concurrent calls are serialized with a transaction-level advisory lock.
+    ///
+    /// # Errors
+    ///
+    /// Errors if any of the statements fail.
+    pub async fn migrate(pool: &PgPool) -> Result<(), SqlxError> {
+        let mut transaction = pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('bc_jobs'))")
+            .execute(&mut *transaction)
+            .await?;
+        transaction.execute(SCHEMA).await?;
+        transaction.commit().await
+    }
+
+    /// Sets how long [`JobQueue::poll`] sleeps between two unsuccessful fetches.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the base delay of the exponential backoff applied to failed jobs.
+    #[must_use]
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    #[must_use]
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Pushes a new job onto the queue and returns its id.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the payload cannot be serialized or the insert fails.
+    pub async fn enqueue<T: Serialize>(&self, job: NewJob<T>) -> Result<i64, SqlxError> {
+        let run_at = job.run_at.map(|t| {
+            t.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+        let insert = sqlx::query(
+            "INSERT INTO bc_jobs (queue, payload, priority, max_attempts, run_at) \
+             VALUES ($1, $2, $3, $4, COALESCE(to_timestamp($5), now())) RETURNING id",
+        )
+        .bind(self.queue.as_ref())
+        .bind(Json(&job.payload))
+        .bind(job.priority)
+        .bind(job.max_attempts)
+        .bind(run_at)
+        .fetch_one(&self.pool);
+        let row = instrument_insert("bc_jobs", 1, insert).await?;
+        row.try_get("id")
+    }
+
+    /// Locks the next runnable job of the queue, if any.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the query fails or the payload cannot be deserialized.
+    pub async fn fetch<T: DeserializeOwned>(&self) -> Result<Option<Job<T>>, SqlxError> {
+        let row = sqlx::query(
+            "UPDATE bc_jobs SET status = 'running', attempts = attempts + 1, heartbeat_at = now() \
+             WHERE id = ( \
+                 SELECT id FROM bc_jobs \
+                 WHERE queue = $1 AND status = 'pending' AND run_at <= now() \
+                 ORDER BY priority DESC, run_at, id \
+                 FOR UPDATE SKIP LOCKED \
+                 LIMIT 1 \
+             ) \
+             RETURNING id, payload, attempts, max_attempts, last_error",
+        )
+        .bind(self.queue.as_ref())
+        .fetch_optional(&self.pool)
+        .await?;
+        row.as_ref().map(Job::from_row).transpose()
+    }
+
+    /// Waits until a job becomes runnable and locks it.
+    ///
+    /// # Errors
+    ///
+    /// Errors if fetching fails.
+    pub async fn poll<T: DeserializeOwned>(&self) -> Result<Job<T>, SqlxError> {
+        loop {
+            if let Some(job) = self.fetch().await? {
+                return Ok(job);
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Signals that the worker processing the job is still alive.
+    ///
+    /// # Errors
+    ///
+    /// Errors with [`SqlxError::RowNotFound`] if the job is no longer claimed by this attempt, or
+    /// if the update fails.
+    pub async fn heartbeat<T>(&self, job: &Job<T>) -> Result<(), SqlxError> {
+        let result = sqlx::query(
+            "UPDATE bc_jobs SET heartbeat_at = now() \
+             WHERE id = $1 AND status = 'running' AND attempts = $2",
+        )
+        .bind(job.id)
+        .bind(job.attempts)
+        .execute(&self.pool)
+        .await?;
+        claimed(result.rows_affected())
+    }
+
+    /// Removes a successfully processed job from the queue.
+    ///
+    /// # Errors
+    ///
+    /// Errors with [`SqlxError::RowNotFound`] if the job is no longer claimed by this attempt,
+    /// e.g. because it was released by [`JobQueue::release_stale`] and fetched again, or if the
+    /// delete fails.
+    pub async fn complete<T>(&self, job: &Job<T>) -> Result<(), SqlxError> {
+        let result = sqlx::query(
+            "DELETE FROM bc_jobs WHERE id = $1 AND status = 'running' AND attempts = $2",
+        )
+        .bind(job.id)
+        .bind(job.attempts)
+        .execute(&self.pool)
+        .await?;
+        claimed(result.rows_affected())
+    }
+
+    /// Records a failed attempt.
+    ///
+    /// The job is rescheduled with exponential backoff, or dead-lettered if it has no attempts
+    /// left. Returns `true` if the job was dead-lettered.
+    ///
+    /// # Errors
+    ///
+    /// Errors with [`SqlxError::RowNotFound`] if the job is no longer claimed by this attempt, or
+    /// if the update fails.
+    pub async fn fail<T>(&self, job: &Job<T>, error: &str) -> Result<bool, SqlxError> {
+        let row = sqlx::query(
+            "UPDATE bc_jobs SET \
+                 status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'pending' END, \
+                 run_at = now() + make_interval(secs => $2::float8 * power(2::float8, GREATEST(attempts - 1, 0))), \
+                 heartbeat_at = NULL, \
+                 last_error = $3 \
+             WHERE id = $1 AND status = 'running' AND attempts = $4 \
+             RETURNING status",
+        )
+        .bind(job.id)
+        .bind(self.retry_delay.as_secs_f64())
+        .bind(error)
+        .bind(job.attempts)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.try_get::<&str, _>("status")? == "dead")
+    }
+
+    /// Releases running jobs whose worker has not sent a heartbeat within `timeout`.
+    ///
+    /// Released jobs count as failed attempts. Returns the number of released jobs.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the update fails.
+    pub async fn release_stale(&self, timeout: Duration) -> Result<u64, SqlxError> {
+        let result = sqlx::query(
+            "UPDATE bc_jobs SET \
+                 status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'pending' END, \
+                 heartbeat_at = NULL, \
+                 last_error = 'heartbeat timed out' \
+             WHERE queue = $1 AND status = 'running' \
+                 AND heartbeat_at < now() - make_interval(secs => $2)",
+        )
+        .bind(self.queue.as_ref())
+        .bind(timeout.as_secs_f64())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Lists the dead-lettered jobs of the queue.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the query fails or a payload cannot be deserialized.
+    pub async fn dead_letters<T: DeserializeOwned>(&self) -> Result<Vec<Job<T>>, SqlxError> {
+        sqlx::query(
+            "SELECT id, payload, attempts, max_attempts, last_error FROM bc_jobs \
+             WHERE queue = $1 AND status = 'dead' ORDER BY id",
+        )
+        .bind(self.queue.as_ref())
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(Job::from_row)
+        .collect()
+    }
+
+    /// Moves a dead-lettered job back to the queue with a fresh set of attempts.
+    ///
+    /// # Errors
+    ///
+    /// Errors with [`SqlxError::RowNotFound`] if the queue has no dead job with the id, or if the
+    /// update fails.
+    pub async fn retry_dead(&self, id: i64) -> Result<(), SqlxError> {
+        let result = sqlx::query(
+            "UPDATE bc_jobs SET status = 'pending', attempts = 0, run_at = now() \
+             WHERE queue = $1 AND id = $2 AND status = 'dead'",
+        )
+        .bind(self.queue.as_ref())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        claimed(result.rows_affected())
+    }
+}
+
+/// Maps an update that matched no row to [`SqlxError::RowNotFound`].
+fn claimed(rows_affected: u64) -> Result<(), SqlxError> {
+    if rows_affected == 0 {
+        return Err(SqlxError::RowNotFound);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Payload {
+        name: String,
+    }
+
+    fn payload(name: &str) -> Payload {
+        Payload {
+            name: name.to_string(),
+        }
+    }
+
+    async fn queue(database: &str) -> JobQueue {
+        let mut config = Config::from_env();
+        config.options = config.options.with_database(database);
+        let queue = JobQueue::connect(config, "test").await.unwrap();
+        sqlx::query("DELETE FROM bc_jobs")
+            .execute(queue.pool())
+            .await
+            .unwrap();
+        queue
+    }
+
+    #[tokio::test]
+    async fn fetch_by_priority() {
+        let queue = queue("job_queue_priority").await;
+        queue.enqueue(NewJob::new(payload("low"))).await.unwrap();
+        queue
+            .enqueue(NewJob::new(payload("high")).with_priority(10))
+            .await
+            .unwrap();
+        queue
+            .enqueue(NewJob::new(payload("later")).with_delay(Duration::from_hours(1)))
+            .await
+            .unwrap();
+
+        let first = queue.fetch::<Payload>().await.unwrap().unwrap();
+        let second = queue.fetch::<Payload>().await.unwrap().unwrap();
+        assert_eq!(first.payload, payload("high"));
+        assert_eq!(first.attempts, 1);
+        assert_eq!(second.payload, payload("low"));
+        // the remaining job is delayed
+        assert!(queue.fetch::<Payload>().await.unwrap().is_none());
+
+        queue.complete(&first).await.unwrap();
+        queue.complete(&second).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetch_skips_locked() {
+        let queue = queue("job_queue_skip_locked").await;
+        for name in ["a", "b"] {
+            queue.enqueue(NewJob::new(payload(name))).await.unwrap();
+        }
+
+        let (a, b) = tokio::join!(queue.fetch::<Payload>(), queue.fetch::<Payload>());
+        let (a, b) = (a.unwrap().unwrap(), b.unwrap().unwrap());
+        assert_ne!(a.id, b.id);
+        assert!(queue.fetch::<Payload>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_dead_lettered() {
+        let queue = queue("job_queue_dead_letter")
+            .await
+            .with_retry_delay(Duration::ZERO);
+        let id = queue
+            .enqueue(NewJob::new(payload("flaky")).with_max_attempts(2))
+            .await
+            .unwrap();
+
+        let job = queue.fetch::<Payload>().await.unwrap().unwrap();
+        assert!(!queue.fail(&job, "first").await.unwrap());
+        let job = queue.fetch::<Payload>().await.unwrap().unwrap();
+        assert_eq!(job.attempts, 2);
+        assert_eq!(job.last_error.as_deref(), Some("first"));
+        assert!(queue.fail(&job, "second").await.unwrap());
+        assert!(queue.fetch::<Payload>().await.unwrap().is_none());
+
+        let dead = queue.dead_letters::<Payload>().await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, id);
+        assert_eq!(dead[0].last_error.as_deref(), Some("second"));
+
+        queue.retry_dead(id).await.unwrap();
+        assert!(matches!(
+            queue.retry_dead(id).await,
+            Err(SqlxError::RowNotFound)
+        ));
+        let job = queue.fetch::<Payload>().await.unwrap().unwrap();
+        assert_eq!(job.attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn stale_job_is_released() {
+        let queue = queue("job_queue_stale").await;
+        queue.enqueue(NewJob::new(payload("stale"))).await.unwrap();
+
+        let job = queue.fetch::<Payload>().await.unwrap().unwrap();
+        queue.heartbeat(&job).await.unwrap();
+        assert_eq!(
+            queue.release_stale(Duration::from_mins(1)).await.unwrap(),
+            0
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            queue
+                .release_stale(Duration::from_millis(10))
+                .await
+                .unwrap(),
+            1
+        );
+        let job = queue.poll::<Payload>().await.unwrap();
+        assert_eq!(job.attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn stale_worker_cannot_settle_new_claim() {
+        let queue = queue("job_queue_stale_worker").await;
+        queue
+            .enqueue(NewJob::new(payload("contested")))
+            .await
+            .unwrap();
+
+        let stale = queue.fetch::<Payload>().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        queue
+            .release_stale(Duration::from_millis(10))
+            .await
+            .unwrap();
+        let current = queue.fetch::<Payload>().await.unwrap().unwrap();
+        assert_eq!(current.id, stale.id);
+
+        assert!(matches!(
+            queue.heartbeat(&stale).await,
+            Err(SqlxError::RowNotFound)
+        ));
+        assert!(matches!(
+            queue.complete(&stale).await,
+            Err(SqlxError::RowNotFound)
+        ));
+        assert!(matches!(
+            queue.fail(&stale, "stale").await,
+            Err(SqlxError::RowNotFound)
+        ));
+
+        queue.complete(&current).await.unwrap();
+        assert!(matches!(
+            queue.complete(&current).await,
+            Err(SqlxError::RowNotFound)
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use super::options::quote_ident;
+
+use bc_batch::Batch;
+use futures_util::Stream;
+use futures_util::stream::try_unfold;
+use sqlx::postgres::PgRow;
+use sqlx::{Error as SqlxError, Executor, FromRow, PgPool, Postgres, Transaction};
+
+/// Name of the server-side cursor, unique within the streaming transaction.
+const CURSOR: &str = "bc_batch_cursor";
+
+enum CursorState {
+    Pending { pool: PgPool, query: String },
+    Open(Transaction<'static, Postgres>),
+    Done,
+}
+
+/// Streams the rows returned by `query` in batches of at most `batch_size` rows.
+///
+/// The query runs behind a server-side cursor inside a dedicated read transaction, so only a
+/// single batch is held in memory at a time. Each batch is collected into `B`, which can be a
+/// plain `Vec<R>` or a batch type derived via `bc_batch::Batch`.
+///
+/// # Panics
+///
+/// Panics if `batch_size` is zero.
+pub fn stream_batches<R, B>(
+    pool: PgPool,
+    query: impl Into<String>,
+    batch_size: usize,
+) -> impl Stream<Item = Result<B, SqlxError>> + Send
+where
+    R: for<'r> FromRow<'r, PgRow> + Send,
+    B: FromIterator<R> + Send,
+{
+    assert!(batch_size > 0, "batch size must be positive");
+    let state = CursorState::Pending {
+        pool,
+        query: query.into(),
+    };
+    let fetch = format!("FETCH FORWARD {batch_size} FROM {CURSOR}");
+
+    try_unfold(state, move |state| {
+        let fetch = fetch.clone();
+        async move {
+            let mut tx = match state {
+                CursorState::Pending { pool, query } => {
+                    let mut tx = pool.begin().await?;
+                    tx.execute(format!("DECLARE {CURSOR} NO SCROLL CURSOR FOR {query}").as_str())
+                        .await?;
+                    tx
+                }
+                CursorState::Open(tx) => tx,
+                CursorState::Done => return Ok(None),
+            };
+
+            let rows = tx.fetch_all(fetch.as_str()).await?;
+            let batch = rows
+                .iter()
+                .map(R::from_row)
+                .collect::<Result<B, SqlxError>>()?;
+            let state = if rows.len() < batch_size {
+                // nothing is written, but committing lets the connection go back to the pool
+                tx.commit().await?;
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                CursorState::Done
+            } else {
+                CursorState::Open(tx)
+            };
+            Ok(Some((batch, state)))
+        }
+    })
+}
+
+/// Streams a table in batches of at most `batch_size` rows, selecting the columns named after
+/// the fields of the batched record, see [`Batch::COLUMNS`].
+///
+/// The table may be qualified with its schema, as in `public.foo`. Every part of the table name
+/// and every column name is quoted, so they are matched case-sensitively.
+///
+/// # Panics
+///
+/// Panics if `batch_size` is zero.
+pub fn stream_table<B>(
+    pool: PgPool,
+    table: &str,
+    batch_size: usize,
+) -> impl Stream<Item = Result<B, SqlxError>> + Send
+where
+    B: Batch + Send,
+    B::Record: for<'r> FromRow<'r, PgRow> + Send,
+{
+    let columns = B::COLUMNS
+        .iter()
+        .map(|column| quote_ident(column))
+        .collect::<Vec<_>>()
+        .join(",");
+    let table = table
+        .split('.')
+        .map(quote_ident)
+        .collect::<Vec<_>>()
+        .join(".");
+    let query = format!("SELECT {columns} FROM {table}");
+    stream_batches::<B::Record, B>(pool, query, batch_size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::postgres::Config;
+    use futures_util::TryStreamExt;
+    use sqlx::Row;
+
+    #[derive(Batch)]
+    struct Foo {
+        id: i32,
+        bar: String,
+    }
+
+    impl FromRow<'_, PgRow> for Foo {
+        fn from_row(row: &PgRow) -> Result<Self, SqlxError> {
+            Ok(Self {
+                id: row.try_get("id")?,
+                bar: row.try_get("bar")?,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_table_in_batches() {
+        let mut config = Config::from_env();
+        config.options = config.options.with_database("stream_batches");
+        let pool = config.connect_with_migration().await.unwrap();
+        sqlx::query("TRUNCATE TABLE foo")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO foo (id, bar) SELECT i, i::TEXT FROM generate_series(1, 10) i")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let batches: Vec<FooBatch> = stream_table(pool.clone(), "foo", 4)
+            .try_collect()
+            .await
+            .unwrap();
+        let sizes: Vec<_> = batches.iter().map(|batch| batch.id.len()).collect();
+        assert_eq!(sizes, [4, 4, 2]);
+        assert_eq!(batches[2].bar, ["9", "10"]);
+
+        let batches: Vec<FooBatch> = stream_table(pool.clone(), "public.foo", 10)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].id.len(), 10);
+
+        let batches: Vec<Vec<(i32,)>> =
+            stream_batches(pool, "SELECT id FROM foo WHERE id <= 8 ORDER BY id", 4)
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1], [(5,), (6,), (7,), (8,)]);
+    }
+}
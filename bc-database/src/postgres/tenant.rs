@@ -0,0 +1,145 @@
+use super::Config;
+use super::options::{quote_ident, search_path};
+
+use sqlx::migrate::Migrator;
+use sqlx::pool::PoolConnection;
+use sqlx::{Error as SqlxError, Executor, PgConnection, PgPool, Postgres};
+use tokio::sync::OnceCell;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Routes database access to per-tenant Postgres schemas.
+///
+/// All tenants share a single pool. Every connection handed out by [`Tenants::acquire`] has its
+/// `search_path` set to the tenant schema followed by `public`, so unqualified table names
+/// resolve within the tenant first. Schemas are created and migrated on first use, once even if
+/// several tasks ask for the same tenant concurrently. Cloning is cheap and clones share the same
+/// pool.
+#[derive(Clone, Debug)]
+pub struct Tenants {
+    pool: PgPool,
+    migrations_path: Arc<str>,
+    schemas: Arc<Mutex<HashMap<String, Arc<OnceCell<()>>>>>,
+}
+
+impl Tenants {
+    /// Connects to Postgres with the provided configuration.
+    ///
+    /// The migrations found under the configured migrations path are applied to every tenant
+    /// schema separately.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection fails or the database cannot be created.
+    pub async fn connect(config: Config) -> Result<Self, SqlxError> {
+        Ok(Self {
+            pool: config.options.connect().await?,
+            migrations_path: Arc::from(config.migrations_path),
+            schemas: Arc::default(),
+        })
+    }
+
+    /// Acquires a connection scoped to the tenant schema, creating and migrating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if no connection can be acquired or the schema cannot be created or migrated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the schema cache lock is poisoned.
+    pub async fn acquire(&self, schema: &str) -> Result<PoolConnection<Postgres>, SqlxError> {
+        let migrated = Arc::clone(
+            self.schemas
+                .lock()
+                .expect("poisoned lock")
+                .entry(schema.to_string())
+                .or_default(),
+        );
+        let mut conn = self.pool.acquire().await?;
+        migrated
+            .get_or_try_init(|| self.migrate(&mut conn, schema))
+            .await?;
+        conn.execute(search_path(schema).as_str()).await?;
+        Ok(conn)
+    }
+
+    /// Returns the schemas that have been migrated so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the schema cache lock is poisoned.
+    #[must_use]
+    pub fn schemas(&self) -> Vec<String> {
+        self.schemas
+            .lock()
+            .expect("poisoned lock")
+            .iter()
+            .filter(|(_, migrated)| migrated.initialized())
+            .map(|(schema, _)| schema.clone())
+            .collect()
+    }
+
+    async fn migrate(&self, conn: &mut PgConnection, schema: &str) -> Result<(), SqlxError> {
+        tracing::info!("creating tenant schema \"{schema}\"");
+        conn.execute(format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(schema)).as_str())
+            .await?;
+        conn.execute(search_path(schema).as_str()).await?;
+
+        tracing::info!("running tenant migration from '{}'", self.migrations_path);
+        let migrator = Migrator::new(self.migrations_path.as_ref().as_ref()).await?;
+        migrator.run(conn).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlx::Row;
+
+    async fn count_foo(conn: &mut PgConnection) -> i64 {
+        sqlx::query("SELECT COUNT(*) FROM foo")
+            .fetch_one(conn)
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    #[tokio::test]
+    async fn tenants_are_isolated() {
+        let mut config = Config::from_env();
+        config.options = config.options.with_database("tenants");
+        let tenants = Tenants::connect(config).await.unwrap();
+
+        let (alpha, beta) = tokio::join!(tenants.acquire("alpha"), tenants.acquire("beta"));
+        let (mut alpha, mut beta) = (alpha.unwrap(), beta.unwrap());
+        alpha.execute("TRUNCATE TABLE foo").await.unwrap();
+        beta.execute("TRUNCATE TABLE foo").await.unwrap();
+
+        alpha
+            .execute("INSERT INTO foo (id, bar) VALUES (1, 'alpha')")
+            .await
+            .unwrap();
+        assert_eq!(count_foo(&mut alpha).await, 1);
+        assert_eq!(count_foo(&mut beta).await, 0);
+
+        // tables missing from the tenant schema fall back to public
+        alpha
+            .execute("CREATE TABLE IF NOT EXISTS public.tenant_shared (id INT)")
+            .await
+            .unwrap();
+        beta.execute("SELECT id FROM tenant_shared").await.unwrap();
+
+        // the search path is set again on every acquire even if a query changed it
+        beta.execute("SET search_path TO alpha").await.unwrap();
+        drop(beta);
+        let mut beta = tenants.acquire("beta").await.unwrap();
+        assert_eq!(count_foo(&mut beta).await, 0);
+
+        let mut schemas = tenants.schemas();
+        schemas.sort();
+        assert_eq!(schemas, ["alpha", "beta"]);
+    }
+}
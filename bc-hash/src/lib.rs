@@ -21,11 +21,39 @@ macro_rules! hash {
     };
 }
 
+/// Generates an incremental hasher, for input that is not available all at once.
+#[cfg(feature = "sha2")]
+macro_rules! hasher {
+    ($package:ident, $name:ident, $hasher:ident, $len:expr) => {
+        #[derive(Clone, Debug, Default)]
+        pub struct $hasher($package::$name);
+
+        impl $hasher {
+            #[must_use]
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            pub fn update<T: AsRef<[u8]>>(&mut self, input: T) {
+                $package::Digest::update(&mut self.0, input.as_ref());
+            }
+
+            #[must_use]
+            pub fn finalize(self) -> [u8; $len] {
+                $package::Digest::finalize(self.0).into()
+            }
+        }
+    };
+}
+
 #[cfg(feature = "sha2")]
 hash!(sha2, Sha256, sha2_256, 32);
 #[cfg(feature = "sha2")]
 hash!(sha2, Sha512, sha2_512, 64);
 
+#[cfg(feature = "sha2")]
+hasher!(sha2, Sha256, Sha2_256, 32);
+
 #[cfg(feature = "sha3")]
 hash!(sha3, Sha3_256, sha3_256, 32);
 #[cfg(feature = "sha3")]
@@ -34,3 +62,16 @@ hash!(sha3, Sha3_512, sha3_512, 64);
 hash!(sha3, Keccak256, keccak256, 32);
 #[cfg(feature = "sha3")]
 hash!(sha3, Keccak512, keccak512, 64);
+
+#[cfg(all(test, feature = "sha2"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn incremental_hashers() {
+        let mut hasher = Sha2_256::new();
+        hasher.update(b"hello ");
+        hasher.update("world");
+        assert_eq!(hasher.finalize(), sha2_256("hello world"));
+    }
+}